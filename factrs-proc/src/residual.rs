@@ -31,6 +31,7 @@ pub fn mark(mut item: ItemImpl) -> TokenStream2 {
     // Build all the things we need from it
     let residual_values = format_ident!("residual{}_values", num);
     let residual_jacobian = format_ident!("residual{}_jacobian", num);
+    let residual_hessian = format_ident!("residual{}_hessian", num);
 
     // If we should add typetag
     let typetag = if cfg!(feature = "serde") {
@@ -77,6 +78,10 @@ pub fn mark(mut item: ItemImpl) -> TokenStream2 {
             fn residual_jacobian(&self, values: &factrs::containers::Values, keys: &[factrs::containers::Key]) -> factrs::linalg::DiffResult<factrs::linalg::VectorX, factrs::linalg::MatrixX> {
                 #residual_trait::#residual_jacobian(self, values, keys)
             }
+
+            fn residual_hessian(&self, values: &factrs::containers::Values, keys: &[factrs::containers::Key]) -> Option<Vec<factrs::linalg::MatrixX>> {
+                #residual_trait::#residual_hessian(self, values, keys)
+            }
         }
     }
 }
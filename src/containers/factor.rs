@@ -4,9 +4,9 @@ use super::{DefaultSymbolHandler, KeyFormatter, Symbol, TypedSymbol};
 use crate::{
     containers::{Key, Values},
     dtype,
-    linalg::{Const, DiffResult, MatrixBlock},
+    linalg::{Const, DiffResult, MatrixBlock, MatrixX, VectorX},
     linear::LinearFactor,
-    noise::{NoiseModel, UnitNoise},
+    noise::{NoiseModel, UnitNoise, UnitNoiseX},
     residuals::Residual,
     robust::{RobustCost, L2},
 };
@@ -66,10 +66,20 @@ pub struct Factor {
 impl Factor {
     /// Compute the error of the factor given a set of values.
     pub fn error(&self, values: &Values) -> dtype {
+        self.robust.loss(self.norm2(values))
+    }
+
+    /// Compute the robust weight of the factor given a set of values.
+    ///
+    /// Values near zero mean the factor is being treated as an outlier.
+    pub fn weight(&self, values: &Values) -> dtype {
+        self.robust.weight(self.norm2(values))
+    }
+
+    // Squared norm of the whitened residual
+    pub(crate) fn norm2(&self, values: &Values) -> dtype {
         let r = self.residual.residual(values, &self.keys);
-        let r = self.noise.whiten_vec(r);
-        let norm2 = r.norm_squared();
-        self.robust.loss(norm2)
+        self.noise.whiten_vec(r).norm_squared()
     }
 
     /// Compute the dimension of the output of the factor.
@@ -111,6 +121,77 @@ impl Factor {
     pub fn keys(&self) -> &[Key] {
         &self.keys
     }
+
+    /// Get the residual of the factor as a concrete type, if it is one.
+    ///
+    /// ```
+    /// # use factrs::{
+    ///    assign_symbols,
+    ///    containers::FactorBuilder,
+    ///    residuals::{BetweenResidual, PriorResidual},
+    ///    traits::*,
+    ///    variables::SO2,
+    /// };
+    /// # assign_symbols!(X: SO2);
+    /// let res = PriorResidual::new(SO2::from_theta(0.1));
+    /// let factor = FactorBuilder::new1(res, X(0)).build();
+    /// assert!(factor.residual_as::<PriorResidual<SO2>>().is_some());
+    /// assert!(factor.residual_as::<BetweenResidual<SO2>>().is_none());
+    /// ```
+    pub fn residual_as<R: Residual>(&self) -> Option<&R> {
+        self.residual.downcast_ref::<R>()
+    }
+
+    // Information matrix of the noise model, W^T W
+    pub(crate) fn information(&self) -> MatrixX {
+        let dim = self.dim_out();
+        let w = self.noise.whiten_mat(MatrixX::identity(dim, dim));
+        w.transpose() * w
+    }
+
+    pub(crate) fn robust_mut(&mut self) -> &mut dyn RobustCost {
+        self.robust.as_mut()
+    }
+
+    pub(crate) fn noise_mut(&mut self) -> &mut dyn NoiseModel {
+        self.noise.as_mut()
+    }
+
+    // Residual before whitening
+    pub(crate) fn residual(&self, values: &Values) -> VectorX {
+        self.residual.residual(values, &self.keys)
+    }
+
+    // Part of the Hessian of the error that Gauss-Newton leaves out, sum_k g_k
+    // H_k where H_k is the Hessian of residual k and g = w W^T W r. None if the
+    // residual doesn't provide Hessians.
+    pub(crate) fn second_order(&self, values: &Values) -> Option<MatrixX> {
+        let hessians = self.residual.residual_hessian(values, &self.keys)?;
+        let r = self.residual(values);
+        let w = self.noise.whiten_mat(MatrixX::identity(r.len(), r.len()));
+        let r = self.noise.whiten_vec(r);
+        let weight = self.robust.weight(r.norm_squared());
+        let g = weight * w.transpose() * r;
+
+        let dim = self.residual.dim_in();
+        Some(
+            hessians
+                .iter()
+                .zip(g.iter())
+                .fold(MatrixX::zeros(dim, dim), |acc, (h, g)| acc + h * *g),
+        )
+    }
+
+    /// Create a factor with unit noise and no robust kernel for a residual
+    /// whose output dimension isn't known at compile time.
+    pub(crate) fn new_dynamic(keys: Vec<Key>, residual: Box<dyn Residual>) -> Self {
+        Self {
+            keys,
+            noise: Box::new(UnitNoiseX::new(residual.dim_out())),
+            residual,
+            robust: Box::new(L2),
+        }
+    }
 }
 
 impl fmt::Debug for Factor {
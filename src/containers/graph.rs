@@ -67,28 +67,145 @@ impl Graph {
         self.factors.is_empty()
     }
 
+    /// Get a factor by its index in the graph.
+    pub fn get(&self, idx: usize) -> Option<&Factor> {
+        self.factors.get(idx)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Factor> {
+        self.factors.iter()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> std::slice::IterMut<'_, Factor> {
+        self.factors.iter_mut()
+    }
+
+    /// Remove all factors matching the predicate, returning them as a new graph.
+    pub fn remove_factors(&mut self, mut f: impl FnMut(&Factor) -> bool) -> Graph {
+        let (removed, kept) = std::mem::take(&mut self.factors)
+            .into_iter()
+            .partition(|factor| f(factor));
+        self.factors = kept;
+        Graph { factors: removed }
+    }
+
+    // Evaluate every factor, in parallel with the rayon feature. Results are
+    // always in the same order as the factors.
+    fn map_factors<T: Send>(&self, f: impl Fn(&Factor) -> T + Send + Sync) -> Vec<T> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            self.factors.par_iter().map(f).collect()
+        }
+
+        #[cfg(not(feature = "rayon"))]
+        {
+            self.factors.iter().map(f).collect()
+        }
+    }
+
     pub fn error(&self, values: &Values) -> dtype {
-        self.factors.iter().map(|f| f.error(values)).sum()
+        // Summed serially so the result doesn't depend on how the work was split
+        self.map_factors(|f| f.error(values)).into_iter().sum()
     }
 
     pub fn linearize(&self, values: &Values) -> LinearGraph {
-        let factors = self.factors.iter().map(|f| f.linearize(values)).collect();
+        let factors = self.map_factors(|f| f.linearize(values));
         LinearGraph::from_vec(factors)
     }
 
+    /// Entries of the second order terms of the Hessian, for residuals that
+    /// provide them (see [Residual::residual_hessian](crate::residuals::Residual::residual_hessian))
+    ///
+    /// These are the terms left out by the Gauss-Newton approximation $J^\top
+    /// J$, summed over all factors. Returned as (row, column, value) triplets
+    /// of the system ordered by `order`, with duplicate entries to be summed.
+    pub fn second_order(&self, values: &Values, order: &ValuesOrder) -> Vec<(usize, usize, dtype)> {
+        let mut triplets = Vec::new();
+        let hessians = self.map_factors(|f| f.second_order(values));
+        for (f, h) in self.factors.iter().zip(hessians) {
+            let Some(h) = h else {
+                continue;
+            };
+
+            // Location of each key in the factor's Hessian, and in the system
+            let blocks = f
+                .keys()
+                .iter()
+                .scan(0, |local, key| {
+                    let dim = values.get_raw(*key).expect("Key missing in values").dim();
+                    let out = (*local, dim, order.get(*key));
+                    *local += dim;
+                    Some(out)
+                })
+                .collect::<Vec<_>>();
+
+            // Keys missing from the order are fixed
+            for (li, dim_i, gi) in blocks.iter() {
+                let Some(gi) = gi else { continue };
+                for (lj, dim_j, gj) in blocks.iter() {
+                    let Some(gj) = gj else { continue };
+                    for i in 0..*dim_i {
+                        for j in 0..*dim_j {
+                            triplets.push((gi.idx + i, gj.idx + j, h[(li + i, lj + j)]));
+                        }
+                    }
+                }
+            }
+        }
+        triplets
+    }
+
     pub fn sparsity_pattern(&self, order: ValuesOrder) -> GraphOrder {
+        let indices = self.jacobian_indices(&order, 0, 0);
         let total_rows = self.factors.iter().map(|f| f.dim_out()).sum();
-        let total_columns = order.dim();
+        GraphOrder::new(order, indices, total_rows, self.len())
+    }
+
+    /// Extend the sparsity pattern of a [GraphOrder] to include factors added
+    /// to the graph since it was computed
+    ///
+    /// The leading factors and variables must be unchanged. Variables appended
+    /// to the order (see [GraphOrder::add_values]) become new columns, and new
+    /// factors new rows, so only the new factors have to be visited.
+    pub fn extend_sparsity_pattern(&self, graph_order: GraphOrder) -> GraphOrder {
+        let GraphOrder {
+            order,
+            mut indices,
+            num_rows,
+            num_factors,
+            ..
+        } = graph_order;
+        indices.extend(self.jacobian_indices(&order, num_factors, num_rows));
+        let total_rows = num_rows
+            + self.factors[num_factors..]
+                .iter()
+                .map(|f| f.dim_out())
+                .sum::<usize>();
+        GraphOrder::new(order, indices, total_rows, self.len())
+    }
 
+    // Nonzero entries of the Jacobian for all factors from `first`, starting at
+    // row `row`
+    fn jacobian_indices(
+        &self,
+        order: &ValuesOrder,
+        first: usize,
+        row: usize,
+    ) -> Vec<(usize, usize)> {
         let mut indices = Vec::<(usize, usize)>::new();
 
-        let _ = self.factors.iter().fold(0, |row, f| {
+        let _ = self.factors[first..].iter().fold(row, |row, f| {
             f.keys().iter().for_each(|key| {
                 (0..f.dim_out()).for_each(|i| {
-                    let Idx {
+                    // Keys missing from the order are fixed
+                    let Some(Idx {
                         idx: col,
                         dim: col_dim,
-                    } = order.get(*key).expect("Key missing in values");
+                    }) = order.get(*key)
+                    else {
+                        return;
+                    };
                     (0..*col_dim).for_each(|j| {
                         indices.push((row + i, col + j));
                     });
@@ -97,14 +214,16 @@ impl Graph {
             row + f.dim_out()
         });
 
-        let (sparsity_pattern, sparsity_order) =
-            SymbolicSparseColMat::try_new_from_indices(total_rows, total_columns, &indices)
-                .expect("Failed to make sparse matrix");
-        GraphOrder {
-            order,
-            sparsity_pattern,
-            sparsity_order,
-        }
+        indices
+    }
+}
+
+impl IntoIterator for Graph {
+    type Item = Factor;
+    type IntoIter = std::vec::IntoIter<Factor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.factors.into_iter()
     }
 }
 
@@ -162,4 +281,49 @@ pub struct GraphOrder {
     pub sparsity_pattern: SymbolicSparseColMat<usize>,
     // Contains the order of values to put into the sparsity pattern
     pub sparsity_order: faer::sparse::ValuesOrder<usize>,
+    // Kept around to extend the sparsity pattern as factors are added
+    indices: Vec<(usize, usize)>,
+    num_rows: usize,
+    num_factors: usize,
+}
+
+impl GraphOrder {
+    pub(crate) fn new(
+        order: ValuesOrder,
+        indices: Vec<(usize, usize)>,
+        num_rows: usize,
+        num_factors: usize,
+    ) -> Self {
+        let (sparsity_pattern, sparsity_order) =
+            SymbolicSparseColMat::try_new_from_indices(num_rows, order.dim(), &indices)
+                .expect("Failed to make sparse matrix");
+        Self {
+            order,
+            sparsity_pattern,
+            sparsity_order,
+            indices,
+            num_rows,
+            num_factors,
+        }
+    }
+
+    /// Append any new variables in `values` to the end of the order
+    ///
+    /// Keeps the location of all existing variables, so the sparsity pattern
+    /// can be extended rather than recomputed.
+    pub fn add_values(&mut self, values: &Values) {
+        self.order.extend_from_values(values);
+    }
+
+    /// Whether the order contains exactly the free variables in `values`
+    pub fn covers(&self, values: &Values) -> bool {
+        let mut num_free = 0;
+        for (key, _) in values.iter().filter(|(k, _)| !values.is_fixed(**k)) {
+            if self.order.get(*key).is_none() {
+                return false;
+            }
+            num_free += 1;
+        }
+        num_free == self.order.len()
+    }
 }
@@ -4,10 +4,10 @@ mod symbol;
 pub use symbol::{DefaultSymbolHandler, Key, KeyFormatter, Symbol, TypedSymbol};
 
 mod values;
-pub use values::{Values, ValuesFormatter};
+pub use values::{ActiveBound, Values, ValuesFormatter};
 
 mod order;
-pub use order::{Idx, ValuesOrder};
+pub use order::{Idx, OrderingStrategy, ValuesOrder};
 
 mod graph;
 pub use graph::{Graph, GraphFormatter, GraphOrder};
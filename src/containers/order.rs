@@ -1,13 +1,19 @@
-use std::collections::hash_map::Iter as HashMapIter;
+use faer::{
+    dyn_stack::{GlobalPodBuffer, PodStack},
+    sparse::{
+        linalg::{amd, colamd},
+        SymbolicSparseColMat,
+    },
+};
+use foldhash::{fast::RandomState, HashMap};
+use indexmap::{map::Iter, IndexMap};
 
-use foldhash::HashMap;
-
-use super::{Key, Symbol, Values};
+use super::{Graph, Key, Symbol, Values};
 
 /// Location of a variable in a list
 ///
-/// Since the map isn't ordered, we need to track both idx and len of each
-/// variable
+/// Since variables have different dimensions, we track both idx and len of
+/// each variable
 #[derive(Debug, Clone)]
 pub struct Idx {
     pub idx: usize,
@@ -17,22 +23,28 @@ pub struct Idx {
 /// Tracks the location of each variable in the graph via an [Idx].
 ///
 /// Likely won't need to ever interface with this unless a custom optimizer is
-/// being implemented. Since the map isn't ordered, we need to track both idx
-/// and len of each variable
+/// being implemented. Variables are kept in the order of their columns, so
+/// iterating over it is deterministic.
 #[derive(Debug, Clone)]
 pub struct ValuesOrder {
-    map: HashMap<Key, Idx>,
+    map: IndexMap<Key, Idx, RandomState>,
     dim: usize,
 }
 
 impl ValuesOrder {
-    pub fn new(map: HashMap<Key, Idx>) -> Self {
+    /// Create an order from the location of each variable
+    pub fn new(map: impl IntoIterator<Item = (Key, Idx)>) -> Self {
+        let mut map = map.into_iter().collect::<IndexMap<_, _, _>>();
+        map.sort_unstable_by(|_, i1, _, i2| i1.idx.cmp(&i2.idx));
         let dim = map.values().map(|idx| idx.dim).sum();
         Self { map, dim }
     }
+
+    /// Create an order from the values, skipping any fixed variables
     pub fn from_values(values: &Values) -> Self {
         let map = values
             .iter()
+            .filter(|(key, _)| !values.is_fixed(**key))
             .scan(0, |idx, (key, val)| {
                 let order = *idx;
                 *idx += val.dim();
@@ -44,17 +56,59 @@ impl ValuesOrder {
                     },
                 ))
             })
-            .collect::<HashMap<Key, Idx>>();
+            .collect::<IndexMap<Key, Idx, RandomState>>();
 
         let dim = map.values().map(|idx| idx.dim).sum();
 
         Self { map, dim }
     }
 
+    /// Create an order with the variables placed in the order of `keys`
+    ///
+    /// Panics if a key is missing from the values.
+    pub fn from_keys(values: &Values, keys: impl IntoIterator<Item = Key>) -> Self {
+        let map = keys
+            .into_iter()
+            .scan(0, |idx, key| {
+                let dim = values.get_raw(key).expect("Key missing in values").dim();
+                let order = *idx;
+                *idx += dim;
+                Some((key, Idx { idx: order, dim }))
+            })
+            .collect::<IndexMap<Key, Idx, RandomState>>();
+
+        Self::new(map)
+    }
+
+    /// Append any variables in `values` that aren't in the order yet to the
+    /// end, in the order they were inserted and skipping fixed variables
+    pub fn extend_from_values(&mut self, values: &Values) {
+        let keys = values
+            .iter()
+            .filter(|(k, _)| !values.is_fixed(**k) && !self.map.contains_key(*k))
+            .map(|(k, v)| (*k, v.dim()))
+            .collect::<Vec<_>>();
+        for (key, dim) in keys {
+            self.map.insert(key, Idx { idx: self.dim, dim });
+            self.dim += dim;
+        }
+    }
+
     pub fn get(&self, symbol: impl Symbol) -> Option<&Idx> {
         self.map.get(&symbol.into())
     }
 
+    /// Find the variable a column of the linear system belongs to
+    pub fn key_at(&self, column: usize) -> Option<Key> {
+        // Entries are in column order, so find the last one starting at or before it
+        let i = self
+            .map
+            .partition_point(|_, idx| idx.idx <= column)
+            .checked_sub(1)?;
+        let (key, idx) = self.map.get_index(i)?;
+        (column < idx.idx + idx.dim).then_some(*key)
+    }
+
     pub fn dim(&self) -> usize {
         self.dim
     }
@@ -67,20 +121,189 @@ impl ValuesOrder {
         self.map.is_empty()
     }
 
-    pub fn iter(&self) -> HashMapIter<Key, Idx> {
+    /// Iterator over all variables, in the order of their columns
+    pub fn iter(&self) -> Iter<'_, Key, Idx> {
         self.map.iter()
     }
 }
 
+/// Strategy for ordering the variables in the linear system
+///
+/// The order of the variables changes the amount of fill-in when factoring the
+/// linear system, and thus the time it takes. Each strategy is deterministic,
+/// so optimizing the same problem twice gives the same results. Sparse
+/// Cholesky solvers eliminate the variables in exactly this order, see
+/// [CholeskySolver](crate::linear::CholeskySolver).
+#[derive(Debug, Clone, Default)]
+pub enum OrderingStrategy {
+    /// Variables in the order they were inserted into the values, exactly as
+    /// [ValuesOrder::from_values]. The other strategies start from this order
+    /// as well, so it decides their ties.
+    Natural,
+    /// Approximate minimum degree on the variable adjacency graph, the default
+    #[default]
+    Amd,
+    /// Column approximate minimum degree on the factor-variable incidence
+    /// matrix
+    Colamd,
+    /// COLAMD, with the given keys forced to the end of the ordering
+    ///
+    /// Useful for incremental problems where the latest poses should be
+    /// eliminated last.
+    ConstrainedColamd(Vec<Key>),
+    /// A user supplied order
+    Custom(ValuesOrder),
+}
+
+impl OrderingStrategy {
+    /// Compute the order of the variables in `values` for the factors in
+    /// `graph`
+    ///
+    /// Fixed variables are left out, except with [OrderingStrategy::Custom]
+    /// where the order is used as is.
+    pub fn order(&self, graph: &Graph, values: &Values) -> ValuesOrder {
+        let keys = values
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| !values.is_fixed(*k))
+            .collect::<Vec<_>>();
+
+        match self {
+            OrderingStrategy::Natural => ValuesOrder::from_values(values),
+            OrderingStrategy::Amd => {
+                let perm = amd_order(graph, &keys);
+                ValuesOrder::from_keys(values, perm.into_iter().map(|i| keys[i]))
+            }
+            OrderingStrategy::Colamd => {
+                let perm = colamd_order(graph, &keys);
+                ValuesOrder::from_keys(values, perm.into_iter().map(|i| keys[i]))
+            }
+            OrderingStrategy::ConstrainedColamd(last) => {
+                let perm = colamd_order(graph, &keys);
+                let (tail, head): (Vec<_>, Vec<_>) = perm
+                    .into_iter()
+                    .map(|i| keys[i])
+                    .partition(|k| last.contains(k));
+                ValuesOrder::from_keys(values, head.into_iter().chain(tail))
+            }
+            OrderingStrategy::Custom(order) => order.clone(),
+        }
+    }
+}
+
+/// Column index of each key, for building the structure of the graph
+fn key_indices(keys: &[Key]) -> HashMap<Key, usize> {
+    keys.iter().enumerate().map(|(i, k)| (*k, i)).collect()
+}
+
+/// AMD ordering of the variable adjacency graph
+fn amd_order(graph: &Graph, keys: &[Key]) -> Vec<usize> {
+    let n = keys.len();
+    let idx = key_indices(keys);
+
+    let mut adjacency = vec![Vec::new(); n];
+    for factor in graph.iter() {
+        let cols = factor
+            .keys()
+            .iter()
+            .filter_map(|k| idx.get(k).copied())
+            .collect::<Vec<_>>();
+        for i in cols.iter() {
+            adjacency[*i].extend(cols.iter().copied());
+        }
+    }
+    let mut col_ptrs = vec![0];
+    let mut row_indices = Vec::new();
+    for (i, mut rows) in adjacency.into_iter().enumerate() {
+        rows.push(i);
+        rows.sort_unstable();
+        rows.dedup();
+        row_indices.extend(rows);
+        col_ptrs.push(row_indices.len());
+    }
+    let nnz = row_indices.len();
+    let a = SymbolicSparseColMat::new_checked(n, n, col_ptrs, None, row_indices);
+
+    let mut perm = vec![0; n];
+    let mut perm_inv = vec![0; n];
+    let req = amd::order_req::<usize>(n, nnz).expect("AMD workspace overflow");
+    amd::order(
+        &mut perm,
+        &mut perm_inv,
+        a.as_ref(),
+        amd::Control::default(),
+        PodStack::new(&mut GlobalPodBuffer::new(req)),
+    )
+    .expect("AMD ordering failed");
+    perm
+}
+
+/// COLAMD ordering of the factor-variable incidence matrix
+fn colamd_order(graph: &Graph, keys: &[Key]) -> Vec<usize> {
+    let n = keys.len();
+    let idx = key_indices(keys);
+
+    let mut incidence = vec![Vec::new(); n];
+    for (i, factor) in graph.iter().enumerate() {
+        for k in factor.keys() {
+            if let Some(j) = idx.get(k) {
+                incidence[*j].push(i);
+            }
+        }
+    }
+    let mut col_ptrs = vec![0];
+    let mut row_indices = Vec::new();
+    for mut rows in incidence.into_iter() {
+        rows.dedup();
+        row_indices.extend(rows);
+        col_ptrs.push(row_indices.len());
+    }
+    let nnz = row_indices.len();
+    let a = SymbolicSparseColMat::new_checked(graph.len(), n, col_ptrs, None, row_indices);
+
+    let mut perm = vec![0; n];
+    let mut perm_inv = vec![0; n];
+    let req = colamd::order_req::<usize>(graph.len(), n, nnz).expect("COLAMD workspace overflow");
+    colamd::order(
+        &mut perm,
+        &mut perm_inv,
+        a.as_ref(),
+        colamd::Control::default(),
+        PodStack::new(&mut GlobalPodBuffer::new(req)),
+    )
+    .expect("COLAMD ordering failed");
+    perm
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        containers::Values,
+        containers::{FactorBuilder, Values},
+        residuals::{BetweenResidual, PriorResidual},
         symbols::X,
         variables::{Variable, VectorVar2, VectorVar3, VectorVar6},
     };
 
+    // Star graph, with X(0) connected to all others
+    fn star() -> (Graph, Values) {
+        let mut graph = Graph::new();
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        let res = PriorResidual::new(VectorVar2::identity());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        for i in 1..6 {
+            values.insert_unchecked(X(i), VectorVar2::identity());
+            let res = BetweenResidual::new(VectorVar2::identity());
+            graph.add_factor(FactorBuilder::new2_unchecked(res, X(0), X(i)).build());
+        }
+        (graph, values)
+    }
+
+    fn position(order: &ValuesOrder, key: X) -> usize {
+        order.get(key).expect("Missing key").idx / 2
+    }
+
     #[test]
     fn from_values() {
         // Create some form of values
@@ -98,5 +321,125 @@ mod test {
         assert_eq!(order.get(X(0)).expect("Missing key").dim, 2);
         assert_eq!(order.get(X(1)).expect("Missing key").dim, 6);
         assert_eq!(order.get(X(2)).expect("Missing key").dim, 3);
+
+        // Find the variable of each column
+        let expected = [
+            (0, X(0)),
+            (1, X(0)),
+            (2, X(1)),
+            (7, X(1)),
+            (8, X(2)),
+            (10, X(2)),
+        ];
+        for (column, key) in expected {
+            assert_eq!(order.key_at(column), Some(key.into()));
+        }
+        assert_eq!(order.key_at(11), None);
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut v = Values::new();
+        v.insert_unchecked(X(2), VectorVar3::identity());
+        v.insert_unchecked(X(0), VectorVar2::identity());
+        v.insert_unchecked(X(1), VectorVar6::identity());
+
+        // Columns follow the order of insertion
+        let order = ValuesOrder::from_values(&v);
+        let keys = order
+            .iter()
+            .map(|(k, idx)| (*k, idx.idx))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![(X(2).into(), 0), (X(0).into(), 3), (X(1).into(), 5)]
+        );
+
+        // Or the keys once sorted
+        v.sort_keys();
+        let order = ValuesOrder::from_values(&v);
+        let keys = order
+            .iter()
+            .map(|(k, idx)| (*k, idx.idx))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![(X(0).into(), 0), (X(1).into(), 2), (X(2).into(), 8)]
+        );
+
+        // Iterating is always in column order
+        let map = HashMap::from_iter(order.iter().rev().map(|(k, idx)| (*k, idx.clone())));
+        let order = ValuesOrder::new(map);
+        let columns = order.iter().map(|(_, idx)| idx.idx).collect::<Vec<_>>();
+        assert_eq!(columns, vec![0, 2, 8]);
+    }
+
+    #[test]
+    fn natural() {
+        let (graph, values) = star();
+        let order = OrderingStrategy::Natural.order(&graph, &values);
+        assert_eq!(order.dim(), 12);
+        for i in 0..6 {
+            assert_eq!(position(&order, X(i)), i as usize);
+        }
+
+        // Follows insertion order, not the keys
+        let mut reversed = Values::new();
+        for i in (0..6).rev() {
+            reversed.insert_unchecked(X(i), VectorVar2::identity());
+        }
+        let order = OrderingStrategy::Natural.order(&graph, &reversed);
+        for i in 0..6 {
+            assert_eq!(position(&order, X(i)), 5 - i as usize);
+        }
+    }
+
+    #[test]
+    fn fill_reducing() {
+        // Eliminating the center before the leaves would fill in everything
+        let (graph, values) = star();
+        for strategy in [OrderingStrategy::Amd, OrderingStrategy::Colamd] {
+            let order = strategy.order(&graph, &values);
+            assert_eq!(order.len(), 6);
+            assert_eq!(order.dim(), 12);
+            assert!(position(&order, X(0)) >= 4);
+        }
+    }
+
+    #[test]
+    fn constrained() {
+        let (graph, values) = star();
+        let strategy = OrderingStrategy::ConstrainedColamd(vec![X(2).into(), X(4).into()]);
+        let order = strategy.order(&graph, &values);
+        assert!(position(&order, X(2)) >= 4);
+        assert!(position(&order, X(4)) >= 4);
+    }
+
+    #[test]
+    fn custom() {
+        let (graph, values) = star();
+        let keys = (0..6).rev().map(|i| X(i).into());
+        let strategy = OrderingStrategy::Custom(ValuesOrder::from_keys(&values, keys));
+        let order = strategy.order(&graph, &values);
+        assert_eq!(position(&order, X(0)), 5);
+        assert_eq!(position(&order, X(5)), 0);
+    }
+
+    #[test]
+    fn fixed() {
+        let (graph, mut values) = star();
+        values.fix(X(0));
+        values.fix(X(3));
+
+        let order = ValuesOrder::from_values(&values);
+        assert_eq!(order.len(), 4);
+        assert!(order.get(X(0)).is_none());
+
+        for strategy in [OrderingStrategy::Natural, OrderingStrategy::Colamd] {
+            let order = strategy.order(&graph, &values);
+            assert_eq!(order.len(), 4);
+            assert_eq!(order.dim(), 8);
+            assert!(order.get(X(3)).is_none());
+        }
     }
 }
@@ -12,7 +12,7 @@ use crate::variables::VariableDtype;
 ///
 /// In it's final form, a Key is what is used for indexing inside of
 /// Values and Factors. Generally it is created from a [Symbol]
-#[derive(Clone, Copy, Eq, Hash, PartialEq, Debug)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq, Ord, PartialOrd, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Key(pub u64);
 
@@ -1,9 +1,14 @@
 use std::{
-    collections::hash_map::Entry, default::Default, fmt, fmt::Write, iter::IntoIterator,
+    collections::{BTreeMap, BTreeSet},
+    default::Default,
+    fmt,
+    fmt::Write,
+    iter::IntoIterator,
     marker::PhantomData,
 };
 
-use foldhash::HashMap;
+use foldhash::fast::RandomState;
+use indexmap::{map::Entry, IndexMap};
 use pad_adapter::PadAdapter;
 
 use super::{
@@ -11,8 +16,10 @@ use super::{
     Key, Symbol, TypedSymbol,
 };
 use crate::{
+    dtype,
+    linalg::{VectorViewX, VectorX},
     linear::LinearValues,
-    variables::{VariableDtype, VariableSafe},
+    variables::{VariableDtype, VariableSafe, VectorVar},
 };
 
 // Since we won't be passing dual numbers through any of this,
@@ -20,7 +27,7 @@ use crate::{
 
 /// Structure to hold the Variables used in the graph.
 ///
-/// Values is essentially a thin wrapper around a Hashmap that maps [Key] ->
+/// Values is essentially a thin wrapper around a map from [Key] ->
 /// [VariableSafe]. If you'd like to define a custom variable to be used in
 /// Values, it must implement [Variable](crate::variables::Variable), and then
 /// will implement [VariableSafe] via a blanket implementation.
@@ -35,10 +42,37 @@ use crate::{
 /// let mut values = Values::new();
 /// values.insert(X(0), x);
 /// ```
-#[derive(Default)]
+///
+/// Variables can also be marked as fixed using [Values::fix]. Fixed variables
+/// are still used when evaluating residuals, but are left out of the linear
+/// system and thus never changed by an optimizer. Vector variables can
+/// additionally be given elementwise bounds using [Values::set_bounds].
+///
+/// Variables are kept in the order they were inserted, so iterating, printing,
+/// serializing, and [ValuesOrder](super::ValuesOrder::from_values) are all
+/// deterministic, and optimizing the same problem twice gives the same results
+/// bit-for-bit. Use [Values::sort_keys] to instead order them by key.
+#[derive(Default, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Values {
-    values: HashMap<Key, Box<dyn VariableSafe>>,
+    values: IndexMap<Key, Box<dyn VariableSafe>, RandomState>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fixed: BTreeSet<Key>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    bounds: BTreeMap<Key, Bounds>,
+}
+
+// Lower and upper bound of a variable
+type Bounds = (Box<dyn VariableSafe>, Box<dyn VariableSafe>);
+
+/// An element of a variable that is at one of its bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveBound {
+    pub key: Key,
+    /// Index of the element in the variable
+    pub idx: usize,
+    /// Whether it's at the upper or lower bound
+    pub upper: bool,
 }
 
 impl Values {
@@ -54,9 +88,8 @@ impl Values {
         self.values.is_empty()
     }
 
-    /// Returns an [std::collections::hash_map::Entry] from the underlying
-    /// HashMap.
-    pub fn entry(&mut self, key: impl Symbol) -> Entry<Key, Box<dyn VariableSafe>> {
+    /// Returns an [indexmap::map::Entry] from the underlying map.
+    pub fn entry(&mut self, key: impl Symbol) -> Entry<'_, Key, Box<dyn VariableSafe>> {
         self.values.entry(key.into())
     }
 
@@ -84,6 +117,13 @@ impl Values {
         self.values.get(&symbol.into()).map(|f| f.as_ref())
     }
 
+    pub(crate) fn get_raw_mut<S>(&mut self, symbol: S) -> Option<&mut dyn VariableSafe>
+    where
+        S: Symbol,
+    {
+        self.values.get_mut(&symbol.into()).map(|f| f.as_mut())
+    }
+
     /// Returns the underlying variable.
     ///
     /// This will return the value if variable is in the graph. Requires a typed
@@ -149,12 +189,179 @@ impl Values {
         S: TypedSymbol<V>,
         V: VariableDtype,
     {
-        self.values
-            .remove(&symbol.into())
+        self.remove_raw(symbol)
             .and_then(|value| value.downcast::<V>().ok())
             .map(|value| *value)
     }
 
+    pub(crate) fn remove_raw<S>(&mut self, symbol: S) -> Option<Box<dyn VariableSafe>>
+    where
+        S: Symbol,
+    {
+        let key = symbol.into();
+        self.fixed.remove(&key);
+        self.bounds.remove(&key);
+        self.values.shift_remove(&key)
+    }
+
+    /// Sort the variables by key, rather than the order they were inserted.
+    ///
+    /// Variables inserted afterwards are still appended to the end.
+    /// ```
+    /// # use factrs::{
+    ///    assign_symbols,
+    ///    containers::{Key, Values},
+    ///    traits::*,
+    ///    variables::SO2,
+    /// };
+    /// # assign_symbols!(X: SO2);
+    /// let mut values = Values::new();
+    /// values.insert(X(1), SO2::identity());
+    /// values.insert(X(0), SO2::identity());
+    /// values.sort_keys();
+    /// let keys: Vec<Key> = values.iter().map(|(k, _)| *k).collect();
+    /// assert_eq!(keys, vec![X(0).into(), X(1).into()]);
+    /// ```
+    pub fn sort_keys(&mut self) {
+        self.values.sort_unstable_keys();
+    }
+
+    /// Mark a variable as fixed, so it won't be changed when optimizing.
+    ///
+    /// ```
+    /// # use factrs::{
+    ///    assign_symbols,
+    ///    containers::Values,
+    ///    traits::*,
+    ///    variables::SO2,
+    /// };
+    /// # assign_symbols!(X: SO2);
+    /// let mut values = Values::new();
+    /// values.insert(X(0), SO2::identity());
+    /// values.fix(X(0));
+    /// assert!(values.is_fixed(X(0)));
+    /// ```
+    pub fn fix(&mut self, symbol: impl Symbol) {
+        self.fixed.insert(symbol.into());
+    }
+
+    /// Allow a fixed variable to be optimized again.
+    pub fn unfix(&mut self, symbol: impl Symbol) {
+        self.fixed.remove(&symbol.into());
+    }
+
+    pub fn is_fixed(&self, symbol: impl Symbol) -> bool {
+        self.fixed.contains(&symbol.into())
+    }
+
+    /// Iterator over the keys of all fixed variables, sorted by key.
+    pub fn fixed(&self) -> impl Iterator<Item = &Key> {
+        self.fixed.iter()
+    }
+
+    /// Bound each element of a vector variable between `lower` and `upper`.
+    ///
+    /// Bounds are only honored by
+    /// [LevenMarquardt](crate::optimizers::LevenMarquardt), which projects
+    /// each step back into the bounds.
+    /// ```
+    /// # use factrs::{
+    ///    assign_symbols,
+    ///    containers::Values,
+    ///    variables::VectorVar2,
+    /// };
+    /// # assign_symbols!(M: VectorVar2);
+    /// let mut values = Values::new();
+    /// values.insert(M(0), VectorVar2::new(1.0, 2.0));
+    /// values.set_bounds(M(0), VectorVar2::new(0.0, 0.0), VectorVar2::new(5.0, 5.0));
+    /// ```
+    pub fn set_bounds<S, const N: usize>(
+        &mut self,
+        symbol: S,
+        lower: VectorVar<N>,
+        upper: VectorVar<N>,
+    ) where
+        S: TypedSymbol<VectorVar<N>>,
+    {
+        self.set_bounds_unchecked(symbol, lower, upper)
+    }
+
+    /// Unchecked version of [Values::set_bounds].
+    pub fn set_bounds_unchecked<S, const N: usize>(
+        &mut self,
+        symbol: S,
+        lower: VectorVar<N>,
+        upper: VectorVar<N>,
+    ) where
+        S: Symbol,
+    {
+        assert!(
+            lower.0.iter().zip(upper.0.iter()).all(|(l, u)| l <= u),
+            "Lower bound must be below upper bound in Values::set_bounds"
+        );
+        self.bounds
+            .insert(symbol.into(), (Box::new(lower), Box::new(upper)));
+    }
+
+    pub fn remove_bounds(&mut self, symbol: impl Symbol) {
+        self.bounds.remove(&symbol.into());
+    }
+
+    pub fn is_bounded(&self, symbol: impl Symbol) -> bool {
+        self.bounds.contains_key(&symbol.into())
+    }
+
+    /// Offset of a bounded variable from its lower bound, along with the
+    /// width of the bounds
+    fn bounds_offset(&self, key: Key) -> Option<(VectorX, VectorX)> {
+        let (lower, upper) = self.bounds.get(&key)?;
+        let value = self.get_raw(key)?;
+        Some((
+            value.ominus_dyn(lower.as_ref()),
+            upper.ominus_dyn(lower.as_ref()),
+        ))
+    }
+
+    /// Shrink a step for a variable so the result stays within its bounds
+    pub(crate) fn project_step(&self, key: Key, delta: VectorViewX) -> VectorX {
+        match self.bounds_offset(key) {
+            Some((offset, width)) => VectorX::from_fn(delta.len(), |i, _| {
+                (offset[i] + delta[i]).clamp(0.0, width[i]) - offset[i]
+            }),
+            None => delta.clone_owned(),
+        }
+    }
+
+    /// All elements of bounded variables that are currently at a bound
+    pub fn active_bounds(&self) -> Vec<ActiveBound> {
+        let tol = dtype::EPSILON.sqrt();
+        let mut active = Vec::new();
+        for key in self.bounds.keys() {
+            let Some((offset, width)) = self.bounds_offset(*key) else {
+                continue;
+            };
+            for i in 0..offset.len() {
+                let scale = 1.0 + width[i].abs();
+                if offset[i] <= tol * scale {
+                    active.push(ActiveBound {
+                        key: *key,
+                        idx: i,
+                        upper: false,
+                    });
+                } else if offset[i] >= width[i] - tol * scale {
+                    active.push(ActiveBound {
+                        key: *key,
+                        idx: i,
+                        upper: true,
+                    });
+                }
+            }
+        }
+        active.sort_by_key(|a| (a.key.0, a.idx));
+        active
+    }
+
+    /// Iterator over all variables, in the order they were inserted.
     pub fn iter(&self) -> impl Iterator<Item = (&Key, &Box<dyn VariableSafe>)> {
         self.values.iter()
     }
@@ -184,10 +391,14 @@ impl Values {
     /// [oplus](crate::variables::Variable::oplus) operation.
     ///
     /// The [LinearValues] need to be setup to have the same keys and each key
-    /// must have a variable of the same length.
+    /// must have a variable of the same length. Fixed variables are left
+    /// untouched.
     pub fn oplus_mut(&mut self, delta: &LinearValues) {
         // TODO: More error checking here
         for (key, value) in delta.iter() {
+            if self.fixed.contains(key) {
+                continue;
+            }
             if let Some(v) = self.values.get_mut(key) {
                 assert!(v.dim() == value.len(), "Dimension mismatch in values oplus",);
                 v.oplus_mut(value);
@@ -269,7 +480,7 @@ impl<KF: KeyFormatter> fmt::Debug for ValuesFormatter<'_, KF> {
 
 impl IntoIterator for Values {
     type Item = (Key, Box<dyn VariableSafe>);
-    type IntoIter = std::collections::hash_map::IntoIter<Key, Box<dyn VariableSafe>>;
+    type IntoIter = indexmap::map::IntoIter<Key, Box<dyn VariableSafe>>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.values.into_iter()
@@ -0,0 +1,462 @@
+use faer::{sparse::SparseColMat, Mat};
+use faer_ext::IntoNalgebra;
+
+use super::KeyIndex;
+use crate::{
+    containers::{Factor, Graph, Key, Values},
+    dtype,
+    linalg::{Matrix2, Matrix3, MatrixX, Vector3, VectorX},
+    linear::{CholeskySolver, LinearSolver, LinearSolverError},
+    residuals::{BetweenResidual, PriorResidual, Residual},
+    variables::{MatrixLieGroup, VariableDtype, SE2, SE3, SO2, SO3},
+};
+
+/// Poses that can be initialized by [chordal]
+///
+/// Splits a pose into a rotation matrix and a translation, where the tangent
+/// space is ordered with the rotation first.
+pub trait ChordalPose: VariableDtype + 'static {
+    /// Dimension of the rotation matrix
+    const DIM_ROT: usize;
+    /// Dimension of the translation, zero for pure rotations
+    const DIM_TRANS: usize;
+
+    fn rotation(&self) -> MatrixX;
+
+    fn translation(&self) -> VectorX;
+
+    /// Rebuild a pose, the rotation is guaranteed to be orthonormal
+    fn from_parts(rotation: &MatrixX, translation: &VectorX) -> Self;
+}
+
+impl ChordalPose for SO2 {
+    const DIM_ROT: usize = 2;
+    const DIM_TRANS: usize = 0;
+
+    fn rotation(&self) -> MatrixX {
+        MatrixX::from_iterator(2, 2, self.to_matrix().iter().copied())
+    }
+
+    fn translation(&self) -> VectorX {
+        VectorX::zeros(0)
+    }
+
+    fn from_parts(rotation: &MatrixX, _translation: &VectorX) -> Self {
+        SO2::from_matrix(Matrix2::from_fn(|i, j| rotation[(i, j)]).as_view())
+    }
+}
+
+impl ChordalPose for SO3 {
+    const DIM_ROT: usize = 3;
+    const DIM_TRANS: usize = 0;
+
+    fn rotation(&self) -> MatrixX {
+        MatrixX::from_iterator(3, 3, self.to_matrix().iter().copied())
+    }
+
+    fn translation(&self) -> VectorX {
+        VectorX::zeros(0)
+    }
+
+    fn from_parts(rotation: &MatrixX, _translation: &VectorX) -> Self {
+        SO3::from_matrix(Matrix3::from_fn(|i, j| rotation[(i, j)]).as_view())
+    }
+}
+
+impl ChordalPose for SE2 {
+    const DIM_ROT: usize = 2;
+    const DIM_TRANS: usize = 2;
+
+    fn rotation(&self) -> MatrixX {
+        self.rot().rotation()
+    }
+
+    fn translation(&self) -> VectorX {
+        VectorX::from_iterator(2, self.xy().iter().copied())
+    }
+
+    fn from_parts(rotation: &MatrixX, translation: &VectorX) -> Self {
+        let theta = rotation[(1, 0)].atan2(rotation[(0, 0)]);
+        SE2::new(theta, translation[0], translation[1])
+    }
+}
+
+impl ChordalPose for SE3 {
+    const DIM_ROT: usize = 3;
+    const DIM_TRANS: usize = 3;
+
+    fn rotation(&self) -> MatrixX {
+        self.rot().rotation()
+    }
+
+    fn translation(&self) -> VectorX {
+        VectorX::from_iterator(3, self.xyz().iter().copied())
+    }
+
+    fn from_parts(rotation: &MatrixX, translation: &VectorX) -> Self {
+        let rot = SO3::from_parts(rotation, &VectorX::zeros(0));
+        let xyz = Vector3::new(translation[0], translation[1], translation[2]);
+        SE3::from_rot_trans(rot, xyz)
+    }
+}
+
+// A between or prior measurement, with the weights of its rotation and
+// translation parts
+struct Measurement<P> {
+    from: Option<usize>,
+    to: usize,
+    value: P,
+    weight_rot: dtype,
+    weight_trans: dtype,
+}
+
+// Average information of the rotation and translation parts of a factor
+fn weights<P: ChordalPose>(factor: &Factor) -> (dtype, dtype) {
+    let info = factor.information();
+    let dim_rot = info.nrows() - P::DIM_TRANS;
+    let weight_rot = info.view((0, 0), (dim_rot, dim_rot)).trace() / dim_rot as dtype;
+    let weight_trans = match P::DIM_TRANS {
+        0 => 0.0,
+        n => info.view((dim_rot, dim_rot), (n, n)).trace() / n as dtype,
+    };
+    (weight_rot, weight_trans)
+}
+
+// Pull out all between and prior measurements on P, indexing each key by when
+// it's first seen
+fn measurements<P: ChordalPose>(graph: &Graph) -> (Vec<Key>, Vec<Measurement<P>>)
+where
+    BetweenResidual<P>: Residual,
+    PriorResidual<P>: Residual,
+{
+    let mut keys = KeyIndex::default();
+    let mut measurements = Vec::new();
+    for factor in graph.iter() {
+        let (from, value) = if let Some(res) = factor.residual_as::<BetweenResidual<P>>() {
+            (Some(keys.index(factor.keys()[0])), res.delta().clone())
+        } else if let Some(res) = factor.residual_as::<PriorResidual<P>>() {
+            (None, res.prior().clone())
+        } else {
+            continue;
+        };
+        let to = keys.index(factor.keys()[from.map_or(0, |_| 1)]);
+        let (weight_rot, weight_trans) = weights::<P>(factor);
+        measurements.push(Measurement {
+            from,
+            to,
+            value,
+            weight_rot,
+            weight_trans,
+        });
+    }
+
+    // Anchor the first pose if nothing else does
+    if !measurements.is_empty() && measurements.iter().all(|m| m.from.is_some()) {
+        measurements.push(Measurement {
+            from: None,
+            to: 0,
+            value: P::identity(),
+            weight_rot: 1.0,
+            weight_trans: 1.0,
+        });
+    }
+
+    (keys.into_keys(), measurements)
+}
+
+// Solve the least squares problem with a block of rows per measurement
+fn solve(
+    triplets: &[(usize, usize, dtype)],
+    b: &MatrixX,
+    cols: usize,
+) -> Result<MatrixX, LinearSolverError> {
+    let a = SparseColMat::try_new_from_triplets(b.nrows(), cols, triplets)
+        .expect("Failed to build initialization system");
+    let b = Mat::from_fn(b.nrows(), b.ncols(), |i, j| b[(i, j)]);
+    let x = CholeskySolver::default().solve_lst_sq(a.as_ref(), b.as_ref())?;
+    Ok(x.as_ref().into_nalgebra().clone_owned())
+}
+
+// Closest rotation matrix in the Frobenius norm
+fn project(m: &MatrixX) -> MatrixX {
+    let svd = m.clone().svd(true, true);
+    let u = svd.u.expect("Missing U in SVD");
+    let v_t = svd.v_t.expect("Missing V^T in SVD");
+    let mut s = MatrixX::identity(m.nrows(), m.ncols());
+    let last = m.nrows() - 1;
+    s[(last, last)] = (&u * &v_t).determinant().signum();
+    u * s * v_t
+}
+
+// Rotations of all poses, by relaxing each to an unconstrained matrix
+fn rotations<P: ChordalPose>(
+    n: usize,
+    measurements: &[Measurement<P>],
+) -> Result<Vec<MatrixX>, LinearSolverError> {
+    // Each row of R_to - R_from R_z is linear in the same rows of the rotations,
+    // so all rows share one system with a right hand side per row
+    let d = P::DIM_ROT;
+    let mut triplets = Vec::new();
+    let mut b = MatrixX::zeros(measurements.len() * d, d);
+    for (k, m) in measurements.iter().enumerate() {
+        let w = m.weight_rot.sqrt();
+        let z = m.value.rotation();
+        for i in 0..d {
+            triplets.push((k * d + i, m.to * d + i, w));
+        }
+        match m.from {
+            Some(from) => {
+                for i in 0..d {
+                    for j in 0..d {
+                        triplets.push((k * d + i, from * d + j, -w * z[(j, i)]));
+                    }
+                }
+            }
+            None => b
+                .view_mut((k * d, 0), (d, d))
+                .copy_from(&(z.transpose() * w)),
+        }
+    }
+
+    let x = solve(&triplets, &b, n * d)?;
+    Ok((0..n)
+        .map(|i| project(&x.view((i * d, 0), (d, d)).transpose()))
+        .collect())
+}
+
+// Translations of all poses, with the rotations held fixed
+fn translations<P: ChordalPose>(
+    n: usize,
+    measurements: &[Measurement<P>],
+    rotations: &[MatrixX],
+) -> Result<Vec<VectorX>, LinearSolverError> {
+    let d = P::DIM_TRANS;
+    if d == 0 {
+        return Ok(vec![VectorX::zeros(0); n]);
+    }
+
+    // t_to - t_from = R_from t_z
+    let mut triplets = Vec::new();
+    let mut b = MatrixX::zeros(measurements.len() * d, 1);
+    for (k, m) in measurements.iter().enumerate() {
+        let w = m.weight_trans.sqrt();
+        let z = m.value.translation();
+        for i in 0..d {
+            triplets.push((k * d + i, m.to * d + i, w));
+        }
+        let rhs = match m.from {
+            Some(from) => {
+                for i in 0..d {
+                    triplets.push((k * d + i, from * d + i, -w));
+                }
+                &rotations[from] * z * w
+            }
+            None => z * w,
+        };
+        b.view_mut((k * d, 0), (d, 1)).copy_from(&rhs);
+    }
+
+    let x = solve(&triplets, &b, n * d)?;
+    Ok((0..n).map(|i| x.rows(i * d, d).column(0).into()).collect())
+}
+
+/// Initialize a pose graph using chordal relaxation
+///
+/// Uses every [BetweenResidual] and [PriorResidual] on `P` in the graph,
+/// ignoring all other factors. First, the constraint that each rotation is
+/// orthonormal is dropped, turning
+/// $$
+/// \sum_{ij} \kappa_{ij} ||R_j - R_i \tilde{R}_{ij}||_F^2
+/// $$
+/// into a linear least squares problem. The results are projected back onto
+/// the closest rotations, then held fixed while solving another linear least
+/// squares problem for the translations. Each measurement is weighted by the
+/// average of the diagonal of its information matrix. See "Initialization
+/// Techniques for 3D SLAM: a Survey on Rotation Estimation and its Use in Pose
+/// Graph Optimization" by Carlone et al. for more details.
+///
+/// Priors anchor the solution, if there are none the first pose is placed at
+/// the identity. Returns a pose for every key seen, or an error if the graph
+/// isn't connected.
+pub fn chordal<P: ChordalPose>(graph: &Graph) -> Result<Values, LinearSolverError>
+where
+    BetweenResidual<P>: Residual,
+    PriorResidual<P>: Residual,
+{
+    let (keys, measurements) = measurements::<P>(graph);
+    log::info!(
+        "Chordal initialization of {} poses from {} measurements",
+        keys.len(),
+        measurements.len()
+    );
+
+    let rotations = rotations(keys.len(), &measurements)?;
+    let translations = translations(keys.len(), &measurements, &rotations)?;
+
+    let mut values = Values::new();
+    for ((key, rot), trans) in keys
+        .into_iter()
+        .zip(rotations.iter())
+        .zip(translations.iter())
+    {
+        values.insert_unchecked(key, P::from_parts(rot, trans));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        linalg::{vectorx, Vector6},
+        noise::GaussianNoise,
+        optimizers::LevenMarquardt,
+        symbols::X,
+        traits::Optimizer,
+        variables::Variable,
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
+
+    // Poses spiraling upwards, with every pose connected to the next and the
+    // one a lap later
+    fn spiral() -> Vec<SE3> {
+        (0..12)
+            .map(|i| {
+                let i = i as dtype;
+                let xi = Vector6::new(0.1 * i, -0.2 * i, 0.5 * i, 2.0, 0.0, 0.3 * i);
+                SE3::exp(xi.as_view())
+            })
+            .collect()
+    }
+
+    // Chain of poses with loop closures four poses ahead, as a macro
+    // since the factor bounds differ for each pose type
+    macro_rules! graph {
+        ($poses:expr, $prior:expr) => {{
+            let poses = $poses;
+            let mut graph = Graph::new();
+            if $prior {
+                let res = PriorResidual::new(poses[0].clone());
+                graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            }
+            let n = poses.len() as u32;
+            for i in 0..n {
+                for j in [i + 1, i + 4] {
+                    if j < n {
+                        let delta = poses[i as usize].minus(&poses[j as usize]).inverse();
+                        let res = BetweenResidual::new(delta);
+                        graph.add_factor(FactorBuilder::new2_unchecked(res, X(i), X(j)).build());
+                    }
+                }
+            }
+            graph
+        }};
+    }
+
+    #[test]
+    fn se3() {
+        let poses = spiral();
+        let values = chordal::<SE3>(&graph!(&poses, true)).expect("Initialization failed");
+        assert_eq!(values.len(), poses.len());
+        for (i, pose) in poses.iter().enumerate() {
+            let x: &SE3 = values.get_unchecked(X(i as u32)).expect("Missing pose");
+            assert_matrix_eq!(x.ominus(pose), VectorX::zeros(6), comp = abs, tol = TOL);
+        }
+    }
+
+    #[test]
+    fn se2_no_prior() {
+        let poses = (0..8)
+            .map(|i| SE2::new(1.3 * i as dtype, i as dtype, (i * i) as dtype / 4.0))
+            .collect::<Vec<_>>();
+        let values = chordal::<SE2>(&graph!(&poses, false)).expect("Initialization failed");
+
+        // First pose is placed at the identity, with everything else relative
+        let x0: &SE2 = values.get_unchecked(X(0)).expect("Missing pose");
+        assert_matrix_eq!(x0.log(), VectorX::zeros(3), comp = abs, tol = TOL);
+        for (i, pose) in poses.iter().enumerate() {
+            let x: &SE2 = values.get_unchecked(X(i as u32)).expect("Missing pose");
+            let expected = poses[0].minus(pose).inverse();
+            assert_matrix_eq!(
+                x.ominus(&expected),
+                VectorX::zeros(3),
+                comp = abs,
+                tol = TOL
+            );
+        }
+    }
+
+    #[test]
+    fn so3() {
+        let poses = spiral()
+            .into_iter()
+            .map(|p| p.rot().clone())
+            .collect::<Vec<_>>();
+        let values = chordal::<SO3>(&graph!(&poses, true)).expect("Initialization failed");
+        for (i, pose) in poses.iter().enumerate() {
+            let x: &SO3 = values.get_unchecked(X(i as u32)).expect("Missing pose");
+            assert_matrix_eq!(x.ominus(pose), VectorX::zeros(3), comp = abs, tol = TOL);
+        }
+    }
+
+    #[test]
+    fn noisy() {
+        // Perturb every measurement, with the loop closures trusted more
+        let poses = spiral();
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(poses[0].clone());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let mut odometry = Values::new();
+        odometry.insert_unchecked(X(0), poses[0].clone());
+        for i in 0..poses.len() - 1 {
+            for (j, sigma) in [(i + 1, 0.1), (i + 4, 0.01)] {
+                if j < poses.len() {
+                    let noise = vectorx![0.05, -0.03, 0.04, 0.1, -0.1, 0.05] * (i as dtype).sin();
+                    let delta = poses[i].minus(&poses[j]).inverse().oplus(noise.as_view());
+                    if j == i + 1 {
+                        let xi: &SE3 = odometry.get_unchecked(X(i as u32)).expect("Missing pose");
+                        let xj = xi.compose(&delta);
+                        odometry.insert_unchecked(X(j as u32), xj);
+                    }
+                    let res = BetweenResidual::new(delta);
+                    let noise = GaussianNoise::<6>::from_scalar_sigma(sigma);
+                    let factor = FactorBuilder::new2_unchecked(res, X(i as u32), X(j as u32))
+                        .noise(noise)
+                        .build();
+                    graph.add_factor(factor);
+                }
+            }
+        }
+
+        // Starting from the chordal estimate ends up at least as well off as
+        // from composed odometry, which starts out far worse
+        let values = chordal::<SE3>(&graph).expect("Initialization failed");
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+        let (result, chordal) = opt.optimize_with_summary(values);
+        assert!(result.is_ok());
+        let (result, odometry) = opt.optimize_with_summary(odometry);
+        assert!(result.is_ok());
+
+        assert!(chordal.error_initial < 0.1 * odometry.error_initial);
+        assert!(
+            chordal.error_final <= odometry.error_final * (1.0 + TOL)
+                || chordal.iterations.len() < odometry.iterations.len()
+        );
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut graph = Graph::new();
+        let res = BetweenResidual::new(SE2::new(0.1, 1.0, 0.0));
+        graph.add_factor(FactorBuilder::new2_unchecked(res.clone(), X(0), X(1)).build());
+        graph.add_factor(FactorBuilder::new2_unchecked(res, X(2), X(3)).build());
+        assert!(chordal::<SE2>(&graph).is_err());
+    }
+}
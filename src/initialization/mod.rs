@@ -0,0 +1,67 @@
+//! Initialization of pose graphs.
+//!
+//! Optimizers only converge to a local minimum, so pose graphs started from
+//! raw odometry, or with no estimates at all, often end up in a poor one. The
+//! methods here build an initial [Values](crate::containers::Values) from the
+//! factors in a [Graph](crate::containers::Graph) alone, ready to be passed to
+//! an optimizer such as [LevenMarquardt](crate::optimizers::LevenMarquardt).
+//!
+//! - [chordal] solves a convex relaxation over the rotations of all poses,
+//!   followed by a linear solve for the translations. See [ChordalPose] for
+//!   the supported pose types.
+//! - [spanning_tree] composes the most certain relative measurements along a
+//!   spanning tree, and works for any variable.
+//!
+//! ```
+//! use factrs::{
+//!     assign_symbols,
+//!     containers::{FactorBuilder, Graph},
+//!     initialization::chordal,
+//!     optimizers::LevenMarquardt,
+//!     residuals::{BetweenResidual, PriorResidual},
+//!     traits::*,
+//!     variables::SE2,
+//! };
+//! # assign_symbols!(X: SE2);
+//! let mut graph = Graph::new();
+//! let res = PriorResidual::new(SE2::identity());
+//! graph.add_factor(FactorBuilder::new1(res, X(0)).build());
+//! for i in 0..4 {
+//!     let res = BetweenResidual::new(SE2::new(1.5708, 1.0, 0.0));
+//!     graph.add_factor(FactorBuilder::new2(res, X(i), X((i + 1) % 4)).build());
+//! }
+//!
+//! let values = chordal::<SE2>(&graph).expect("Initialization failed");
+//! let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+//! let result = opt.optimize(values).expect("Optimization failed");
+//! ```
+use foldhash::HashMap;
+
+use crate::containers::Key;
+
+mod chordal;
+pub use chordal::{chordal, ChordalPose};
+
+mod spanning_tree;
+pub use spanning_tree::spanning_tree;
+
+// Index of each key in the graph, by when it's first seen
+#[derive(Default)]
+struct KeyIndex {
+    keys: Vec<Key>,
+    index: HashMap<Key, usize>,
+}
+
+impl KeyIndex {
+    fn index(&mut self, key: Key) -> usize {
+        *self.index.entry(key).or_insert_with(|| {
+            self.keys.push(key);
+            self.keys.len() - 1
+        })
+    }
+
+    // All keys seen, in order of their index
+    fn into_keys(self) -> Vec<Key> {
+        self.keys
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+use super::KeyIndex;
+use crate::{
+    containers::{Graph, Values},
+    residuals::{BetweenResidual, PriorResidual, Residual},
+    variables::VariableDtype,
+};
+
+// Minimal union-find, used by Kruskal's algorithm to detect cycles
+struct DisjointSet {
+    parent: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    // Returns false if both were already in the same set
+    fn union(&mut self, i: usize, j: usize) -> bool {
+        let (i, j) = (self.find(i), self.find(j));
+        self.parent[i] = j;
+        i != j
+    }
+}
+
+/// Initialize a graph by composing measurements along a spanning tree
+///
+/// Builds a minimum spanning tree over every [BetweenResidual] on `P` in the
+/// graph, where the cost of each edge is the inverse of the trace of its
+/// information matrix, so the tree keeps the most certain measurements. The
+/// relative measurements are then composed outwards from a root in each
+/// connected component. Roots are placed at the value of the first
+/// [PriorResidual] on `P` in the component, or at the identity if there are
+/// none. All other factors are ignored.
+///
+/// This is cheap and works for any variable, but errors accumulate along the
+/// tree. For poses, [chordal](super::chordal) uses all measurements at once
+/// and is often much better.
+///
+/// ```
+/// use factrs::{
+///     assign_symbols,
+///     containers::{FactorBuilder, Graph},
+///     initialization::spanning_tree,
+///     noise::GaussianNoise,
+///     residuals::BetweenResidual,
+///     traits::*,
+///     variables::SE2,
+/// };
+/// # assign_symbols!(X: SE2);
+/// let mut graph = Graph::new();
+/// let odom = BetweenResidual::new(SE2::new(0.1, 1.0, 0.0));
+/// let noise = GaussianNoise::<3>::from_scalar_sigma(0.1);
+/// graph.add_factor(FactorBuilder::new2(odom.clone(), X(0), X(1)).noise(noise.clone()).build());
+/// graph.add_factor(FactorBuilder::new2(odom, X(1), X(2)).noise(noise).build());
+///
+/// // Less certain loop closure is left out of the tree
+/// let close = BetweenResidual::new(SE2::new(0.0, 0.0, 0.0));
+/// let noise = GaussianNoise::<3>::from_scalar_sigma(10.0);
+/// graph.add_factor(FactorBuilder::new2(close, X(0), X(2)).noise(noise).build());
+///
+/// let values = spanning_tree::<SE2>(&graph);
+/// let x2: &SE2 = values.get(X(2)).unwrap();
+/// assert!((x2.xy()[0] - 1.995).abs() < 1e-3);
+/// ```
+pub fn spanning_tree<P: VariableDtype + 'static>(graph: &Graph) -> Values
+where
+    BetweenResidual<P>: Residual,
+    PriorResidual<P>: Residual,
+{
+    let mut keys = KeyIndex::default();
+    let mut edges = Vec::new();
+    let mut priors = Vec::new();
+    for factor in graph.iter() {
+        if let Some(res) = factor.residual_as::<BetweenResidual<P>>() {
+            let (i, j) = (keys.index(factor.keys()[0]), keys.index(factor.keys()[1]));
+            let cost = 1.0 / factor.information().trace();
+            edges.push((cost, i, j, res.delta()));
+        } else if let Some(res) = factor.residual_as::<PriorResidual<P>>() {
+            priors.push((keys.index(factor.keys()[0]), res.prior()));
+        }
+    }
+    let keys = keys.into_keys();
+    let n = keys.len();
+
+    // Kruskal's, a stable sort keeps ties in graph order
+    edges.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut set = DisjointSet::new(n);
+    let mut tree = vec![Vec::new(); n];
+    for &(_, i, j, delta) in &edges {
+        if set.union(i, j) {
+            tree[i].push((j, delta, true));
+            tree[j].push((i, delta, false));
+        }
+    }
+    log::info!(
+        "Spanning tree initialization of {} variables from {} of {} measurements",
+        n,
+        tree.iter().map(Vec::len).sum::<usize>() / 2,
+        edges.len()
+    );
+
+    // Root each component at a prior if it has one, then fill in the rest
+    let mut estimates: Vec<Option<P>> = vec![None; n];
+    let roots = priors
+        .into_iter()
+        .map(|(i, prior)| (i, prior.clone()))
+        .chain((0..n).map(|i| (i, P::identity())));
+    for (root, value) in roots {
+        if estimates[root].is_some() {
+            continue;
+        }
+        estimates[root] = Some(value);
+
+        let mut queue = VecDeque::from([root]);
+        while let Some(i) = queue.pop_front() {
+            for &(j, delta, forward) in &tree[i] {
+                if estimates[j].is_none() {
+                    let xi = estimates[i].as_ref().expect("Parent is always set first");
+                    let xj = if forward {
+                        xi.compose(delta)
+                    } else {
+                        xi.compose(&delta.inverse())
+                    };
+                    estimates[j] = Some(xj);
+                    queue.push_back(j);
+                }
+            }
+        }
+    }
+
+    let mut values = Values::new();
+    for (key, x) in keys.into_iter().zip(estimates) {
+        values.insert_unchecked(key, x.expect("Every component has a root"));
+    }
+    values
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        dtype,
+        linalg::{vectorx, VectorX},
+        noise::GaussianNoise,
+        symbols::X,
+        variables::{Variable, VectorVar2, SE2, SO3},
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
+
+    #[test]
+    fn prefers_information() {
+        // Odometry around a square, with a bad but uncertain loop closure
+        let mut graph = Graph::new();
+        let pi = std::f64::consts::PI as dtype;
+        let odom = BetweenResidual::new(SE2::new(pi / 2.0, 1.0, 0.0));
+        let noise = GaussianNoise::<3>::from_scalar_sigma(0.1);
+        for i in 0..3 {
+            let factor = FactorBuilder::new2_unchecked(odom.clone(), X(i), X(i + 1))
+                .noise(noise.clone())
+                .build();
+            graph.add_factor(factor);
+        }
+        let close = BetweenResidual::new(SE2::new(0.0, 5.0, 5.0));
+        let noise = GaussianNoise::<3>::from_scalar_sigma(1.0);
+        graph.add_factor(
+            FactorBuilder::new2_unchecked(close, X(3), X(0))
+                .noise(noise)
+                .build(),
+        );
+
+        let values = spanning_tree::<SE2>(&graph);
+        let expected = [
+            SE2::new(0.0, 0.0, 0.0),
+            SE2::new(pi / 2.0, 1.0, 0.0),
+            SE2::new(pi, 1.0, 1.0),
+            SE2::new(-pi / 2.0, 0.0, 1.0),
+        ];
+        for (i, e) in (0..).zip(expected.iter()) {
+            let x: &SE2 = values.get_unchecked(X(i)).expect("Missing variable");
+            assert_matrix_eq!(x.ominus(e), VectorX::zeros(3), comp = abs, tol = TOL);
+        }
+    }
+
+    #[test]
+    fn prior_and_reversed() {
+        // Measurements pointing towards the root are inverted
+        let mut graph = Graph::new();
+        let prior = SO3::exp(vectorx![0.1, 0.2, 0.3].as_view());
+        let delta = SO3::exp(vectorx![-0.4, 0.0, 0.5].as_view());
+        let res = BetweenResidual::new(delta.clone());
+        graph.add_factor(FactorBuilder::new2_unchecked(res, X(1), X(0)).build());
+        let res = PriorResidual::new(prior.clone());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+
+        let values = spanning_tree::<SO3>(&graph);
+        let x0: &SO3 = values.get_unchecked(X(0)).expect("Missing variable");
+        let x1: &SO3 = values.get_unchecked(X(1)).expect("Missing variable");
+        assert_matrix_eq!(x0.ominus(&prior), VectorX::zeros(3), comp = abs, tol = TOL);
+        assert_matrix_eq!(
+            x1.compose(&delta).ominus(x0),
+            VectorX::zeros(3),
+            comp = abs,
+            tol = TOL
+        );
+    }
+
+    #[test]
+    fn forest() {
+        // Each component gets its own root
+        let mut graph = Graph::new();
+        let res = BetweenResidual::new(VectorVar2::new(1.0, 2.0));
+        graph.add_factor(FactorBuilder::new2_unchecked(res.clone(), X(0), X(1)).build());
+        graph.add_factor(FactorBuilder::new2_unchecked(res, X(2), X(3)).build());
+        let res = PriorResidual::new(VectorVar2::new(-1.0, 0.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(3)).build());
+
+        let values = spanning_tree::<VectorVar2>(&graph);
+        let expected = [(0.0, 0.0), (1.0, 2.0), (-2.0, -2.0), (-1.0, 0.0)];
+        assert_eq!(values.len(), expected.len());
+        for (i, (x, y)) in (0..).zip(expected) {
+            let v: &VectorVar2 = values.get_unchecked(X(i)).expect("Missing variable");
+            assert_matrix_eq!(v.0, vectorx![x, y], comp = abs, tol = TOL);
+        }
+    }
+}
@@ -2,11 +2,13 @@
 //! graphs, specifically geared for sensor fusion in robotics.
 //!
 //! Currently, it supports the following features
-//! - Gauss-Newton & Levenberg-Marquadt Optimizers
+//! - Gauss-Newton, Levenberg-Marquadt & Dogleg Optimizers
+//! - Incremental optimization via iSAM2
 //! - Common Lie Groups supported (SO2, SO3, SE2, SE3) with optimization in Lie
 //!   Algebras
 //! - Automatic differentiation via dual numbers
 //! - First class support for robust kernels
+//! - Initialization via chordal relaxation or spanning trees
 //! - Serialization of graphs & variables via optional serde support
 //! - Easy conversion to rerun types for simple visualization
 //!
@@ -97,6 +99,7 @@ extern crate self as factrs;
 pub use factrs_proc::{fac, mark};
 
 pub mod containers;
+pub mod initialization;
 pub mod linalg;
 pub mod linear;
 pub mod noise;
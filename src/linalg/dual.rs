@@ -9,6 +9,8 @@ impl<G: RealField + num_dual::DualNum<dtype> + SupersetOf<dtype> + Copy> Numeric
 
 pub type DualVector<N> = num_dual::DualVec<dtype, dtype, N>;
 pub type DualScalar = num_dual::Dual<dtype, dtype>;
+/// Second order dual number, carrying both a gradient and a Hessian
+pub type Dual2Vector<N> = num_dual::Dual2Vec<dtype, dtype, N>;
 
 /// Make allocator binds easier for dual numbers
 pub trait DualAllocator<N: Dim>:
@@ -8,10 +8,11 @@
 //! - a [Diff] trait to help with numerical and forward-mode differentiation
 //! - Forward mode differentiator [ForwardProp]
 //! - Numerical differentiator [NumericalDiff]
+//! - Second order differentiator [SecondOrderProp], for residual Hessians
 use crate::dtype;
 
 mod dual;
-pub use dual::{DualAllocator, DualScalar, DualVector, Numeric};
+pub use dual::{Dual2Vector, DualAllocator, DualScalar, DualVector, Numeric};
 // Dual numbers
 pub use num_dual::Derivative;
 
@@ -149,3 +150,6 @@ pub use numerical_diff::NumericalDiff;
 
 mod forward_prop;
 pub use forward_prop::ForwardProp;
+
+mod second_order;
+pub use second_order::{SecondOrderProp, SecondOrderResult};
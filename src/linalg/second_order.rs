@@ -0,0 +1,156 @@
+use paste::paste;
+
+use super::{
+    dual::{Dual2Vector, DualAllocator},
+    Numeric,
+};
+use crate::{
+    linalg::{Const, DefaultAllocator, DimName, MatrixX, VectorDim, VectorX},
+    variables::{Variable, VariableDtype},
+};
+
+/// Value, Jacobian and the Hessian of each output of a vector valued function
+#[derive(Debug, Clone)]
+pub struct SecondOrderResult {
+    pub value: VectorX,
+    pub jacobian: MatrixX,
+    /// Hessian of each output, with respect to the stacked inputs
+    pub hessians: Vec<MatrixX>,
+}
+
+/// Second order forward mode differentiator
+///
+/// Like [ForwardProp](super::ForwardProp), but uses second order dual numbers
+/// ([Dual2Vector]) to compute the Hessian of each output on top of the
+/// Jacobian. These are exact, and are used for second order optimization of
+/// strongly nonlinear residuals. The generic parameter `N` is the total
+/// dimension of the inputs.
+///
+/// Computing all Hessians costs roughly `N` times as much as the Jacobian, so
+/// this is best left to residuals that need it.
+///
+/// ```
+/// use factrs::{
+///     linalg::{vectorx, Const, Numeric, SecondOrderProp, VectorX},
+///     traits::*,
+///     variables::VectorVar2,
+/// };
+///
+/// // Range to the origin
+/// fn f<T: Numeric>(x: VectorVar2<T>) -> VectorX<T> {
+///     vectorx![x.0.norm()]
+/// }
+///
+/// let x = VectorVar2::new(3.0, 4.0);
+/// let result = SecondOrderProp::<Const<2>>::hessian_1(f, &x);
+/// assert_eq!(result.value, vectorx![5.0]);
+/// ```
+pub struct SecondOrderProp<N: DimName> {
+    _phantom: std::marker::PhantomData<N>,
+}
+
+macro_rules! second_order_maker {
+    ($num:expr, $( ($name:ident: $var:ident) ),*) => {
+        paste! {
+            #[doc=concat!("Compute the value, Jacobian, and Hessians of a function with ", $num, " inputs")]
+            #[allow(unused_assignments)]
+            pub fn [<hessian_ $num>]<$( $var: VariableDtype, )* F: Fn($($var::Alias<Dual2Vector<N>>,)*) -> VectorX<Dual2Vector<N>>>
+                    (f: F, $($name: &$var,)*) -> SecondOrderResult {
+                // Prepare variables
+                let mut curr_dim = 0;
+                $(
+                    let $name: $var::Alias<Dual2Vector<N>> = $name.dual2(curr_dim);
+                    curr_dim += $name.dim();
+                )*
+
+                // Compute residual
+                let res = f($($name,)*);
+
+                // Pull out the first and second derivatives of each output
+                let n = VectorDim::<N>::zeros().shape_generic().0;
+                let mut jacobian = MatrixX::zeros(res.len(), N::USIZE);
+                let mut hessians = Vec::with_capacity(res.len());
+                for (k, r) in res.iter().copied().enumerate() {
+                    jacobian
+                        .row_mut(k)
+                        .copy_from(&r.v1.unwrap_generic(Const::<1>, n));
+                    let mut hessian = MatrixX::zeros(N::USIZE, N::USIZE);
+                    hessian.copy_from(&r.v2.unwrap_generic(n, n));
+                    hessians.push(hessian);
+                }
+
+                SecondOrderResult {
+                    value: res.map(|r| r.re),
+                    jacobian,
+                    hessians,
+                }
+            }
+        }
+    };
+}
+
+impl<N: DimName> SecondOrderProp<N>
+where
+    DefaultAllocator: DualAllocator<N>,
+    Dual2Vector<N>: Numeric,
+{
+    second_order_maker!(1, (v1: V1));
+    second_order_maker!(2, (v1: V1), (v2: V2));
+    second_order_maker!(3, (v1: V1), (v2: V2), (v3: V3));
+    second_order_maker!(4, (v1: V1), (v2: V2), (v3: V3), (v4: V4));
+    second_order_maker!(5, (v1: V1), (v2: V2), (v3: V3), (v4: V4), (v5: V5));
+    second_order_maker!(6, (v1: V1), (v2: V2), (v3: V3), (v4: V4), (v5: V5), (v6: V6));
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        linalg::vectorx,
+        variables::{MatrixLieGroup, VectorVar2, SE2},
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-5;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-2;
+
+    // Bearing from a pose to a landmark
+    fn bearing<T: Numeric>(x: SE2<T>, l: VectorVar2<T>) -> VectorX<T> {
+        let p = x.inverse().apply(l.0.as_view());
+        vectorx![p[1].atan2(p[0])]
+    }
+
+    #[test]
+    fn hessian() {
+        let x = SE2::new(0.3, 1.0, -2.0);
+        let l = VectorVar2::new(4.0, 2.5);
+        let result = SecondOrderProp::<Const<5>>::hessian_2(bearing, &x, &l);
+
+        // Compare to central differences in the tangent space
+        let f = |d: VectorX| {
+            let x = x.oplus(d.rows(0, 3));
+            let l = l.oplus(d.rows(3, 2));
+            bearing(x, l)[0]
+        };
+        let h = 1e-3;
+        let e = |i| VectorX::from_fn(5, |k, _| if k == i { h } else { 0.0 });
+        let jacobian = MatrixX::from_fn(1, 5, |_, i| (f(e(i)) - f(-e(i))) / (2.0 * h));
+        let hessian = MatrixX::from_fn(5, 5, |i, j| {
+            let (ei, ej) = (e(i), e(j));
+            (f(&ei + &ej) - f(&ei - &ej) - f(&ej - &ei) + f(-&ei - &ej)) / (4.0 * h * h)
+        });
+
+        assert_matrix_eq!(
+            result.value,
+            bearing(x.clone(), l.clone()),
+            comp = abs,
+            tol = TOL
+        );
+        assert_matrix_eq!(result.jacobian, jacobian, comp = abs, tol = TOL);
+        assert_eq!(result.hessians.len(), 1);
+        assert_matrix_eq!(result.hessians[0], hessian, comp = abs, tol = 1e2 * TOL);
+    }
+}
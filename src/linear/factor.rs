@@ -33,19 +33,13 @@ impl LinearFactor {
     }
 
     pub fn error(&self, vector: &LinearValues) -> dtype {
+        // Keys missing from the vector are fixed, ie have no update
         let ax: VectorX = self
             .keys
             .iter()
             .enumerate()
-            .map(|(idx, key)| {
-                self.a.mul(
-                    idx,
-                    vector
-                        .get(*key)
-                        .expect("Missing key in LinearValues::error"),
-                )
-            })
-            .sum();
+            .filter_map(|(idx, key)| vector.get(*key).map(|v| self.a.mul(idx, v)))
+            .fold(VectorX::zeros(self.dim_out()), |acc, v| acc + v);
         (ax - &self.b).norm_squared() / 2.0
     }
 }
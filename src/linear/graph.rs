@@ -1,4 +1,4 @@
-use faer::sparse::{SparseColMat, SymbolicSparseColMat};
+use faer::sparse::SparseColMat;
 use faer_ext::IntoFaer;
 
 use super::LinearValues;
@@ -39,17 +39,20 @@ impl LinearGraph {
     // reuse?
     pub fn sparsity_pattern(&self, order: ValuesOrder) -> GraphOrder {
         let total_rows = self.factors.iter().map(|f| f.dim_out()).sum();
-        let total_columns = order.dim();
 
         let mut indices = Vec::<(usize, usize)>::new();
 
         let _ = self.factors.iter().fold(0, |row, f| {
             f.keys.iter().for_each(|key| {
                 (0..f.dim_out()).for_each(|i| {
-                    let Idx {
+                    // Keys missing from the order are fixed
+                    let Some(Idx {
                         idx: col,
                         dim: col_dim,
-                    } = order.get(*key).expect("Key missing in values");
+                    }) = order.get(*key)
+                    else {
+                        return;
+                    };
                     (0..*col_dim).for_each(|j| {
                         indices.push((row + i, col + j));
                     });
@@ -58,15 +61,7 @@ impl LinearGraph {
             row + f.dim_out()
         });
 
-        let (sparsity_pattern, sparsity_order) =
-            SymbolicSparseColMat::try_new_from_indices(total_rows, total_columns, &indices)
-                .expect("Failed to create sparse matrix");
-
-        GraphOrder {
-            order,
-            sparsity_pattern,
-            sparsity_order,
-        }
+        GraphOrder::new(order, indices, total_rows, self.factors.len())
     }
 
     /// Computes J and r for use in solver
@@ -87,8 +82,11 @@ impl LinearGraph {
         let mut values: Vec<dtype> = Vec::new();
         // Iterate over all factors
         let _ = self.factors.iter().fold(0, |row, f| {
-            // Iterate over keys
+            // Iterate over keys, skipping fixed ones
             (0..f.keys.len()).for_each(|idx| {
+                if graph_order.order.get(f.keys[idx]).is_none() {
+                    return;
+                }
                 // Iterate over rows, then column elements
                 f.a.get_block(idx).row_iter().for_each(|r| {
                     r.iter().for_each(|val| {
@@ -0,0 +1,208 @@
+use std::ops::Mul;
+
+use faer::{
+    prelude::SpSolver,
+    sparse::linalg::solvers::{Cholesky, SymbolicCholesky},
+    Mat,
+};
+use faer_ext::IntoNalgebra;
+
+use crate::{
+    containers::{Graph, Key, Symbol, Values, ValuesOrder},
+    dtype,
+    linalg::{DiffResult, MatrixX},
+};
+
+/// Marginal covariances of the variables in a graph
+///
+/// The graph is linearized at the given values, and the sparse Cholesky
+/// factorization of the information matrix $J^\top J$ is kept around. Rather
+/// than inverting the whole information matrix, each query only solves for the
+/// columns of the covariance that are needed.
+///
+/// Covariances are expressed in the tangent space of each variable, about the
+/// given values. They follow the same convention as
+/// [oplus](crate::variables::Variable::oplus), so are right-handed by default
+/// or left-handed with the `left` feature.
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{FactorBuilder, Graph, Values},
+///    linear::Marginals,
+///    noise::GaussianNoise,
+///    residuals::PriorResidual,
+///    traits::*,
+///    variables::SE2,
+/// };
+/// # assign_symbols!(X: SE2);
+/// let mut graph = Graph::new();
+/// let noise = GaussianNoise::<3>::from_scalar_sigma(0.1);
+/// let factor = FactorBuilder::new1(PriorResidual::new(SE2::identity()), X(0))
+///     .noise(noise)
+///     .build();
+/// graph.add_factor(factor);
+///
+/// let mut values = Values::new();
+/// values.insert(X(0), SE2::identity());
+///
+/// let marginals = Marginals::new(&graph, &values).expect("Underconstrained");
+/// let cov = marginals.covariance(X(0)).expect("Missing X(0)");
+/// ```
+pub struct Marginals {
+    order: ValuesOrder,
+    cholesky: Cholesky<usize, dtype>,
+}
+
+impl Marginals {
+    /// Linearize and factor the graph about the values
+    ///
+    /// Returns None if the information matrix isn't positive definite, ie the
+    /// system is underconstrained.
+    pub fn new(graph: &Graph, values: &Values) -> Option<Self> {
+        let graph_order = graph.sparsity_pattern(ValuesOrder::from_values(values));
+        let DiffResult { value: _, diff: j } =
+            graph.linearize(values).residual_jacobian(&graph_order);
+
+        let jtj = j
+            .as_ref()
+            .transpose()
+            .to_col_major()
+            .expect("Failed to transpose J")
+            .mul(j.as_ref());
+
+        let symbolic = SymbolicCholesky::try_new(jtj.symbolic(), faer::Side::Lower).ok()?;
+        let cholesky =
+            Cholesky::try_new_with_symbolic(symbolic, jtj.as_ref(), faer::Side::Lower).ok()?;
+
+        Some(Self {
+            order: graph_order.order,
+            cholesky,
+        })
+    }
+
+    /// Marginal covariance of a single variable
+    ///
+    /// Returns None if the key isn't in the values.
+    pub fn covariance(&self, key: impl Symbol) -> Option<MatrixX> {
+        self.joint_covariance(&[key.into()])
+    }
+
+    /// Joint covariance of a set of variables
+    ///
+    /// The blocks of the covariance are in the same order as the keys. Returns
+    /// None if any of the keys aren't in the values.
+    pub fn joint_covariance(&self, keys: &[Key]) -> Option<MatrixX> {
+        let idx = keys
+            .iter()
+            .map(|k| self.order.get(*k).cloned())
+            .collect::<Option<Vec<_>>>()?;
+        let dim = idx.iter().map(|i| i.dim).sum();
+
+        // Only solve for the columns we need
+        let mut rhs = Mat::<dtype>::zeros(self.order.dim(), dim);
+        let mut col = 0;
+        for i in idx.iter() {
+            for j in 0..i.dim {
+                rhs.write(i.idx + j, col, 1.0);
+                col += 1;
+            }
+        }
+        let cols = self.cholesky.solve(&rhs);
+        let cols = cols.as_ref().into_nalgebra();
+
+        let mut cov = MatrixX::zeros(dim, dim);
+        let mut row = 0;
+        for i in idx.iter() {
+            cov.rows_mut(row, i.dim).copy_from(&cols.rows(i.idx, i.dim));
+            row += i.dim;
+        }
+
+        Some(cov)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        linalg::Matrix2,
+        noise::GaussianNoise,
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::X,
+        variables::{Variable, VectorVar2},
+    };
+
+    fn chain() -> (Graph, Values) {
+        let mut graph = Graph::new();
+        let noise = GaussianNoise::<2>::from_diag_sigmas(0.1, 0.2);
+        let factor =
+            FactorBuilder::new1_unchecked(PriorResidual::new(VectorVar2::identity()), X(0))
+                .noise(noise)
+                .build();
+        graph.add_factor(factor);
+
+        let noise = GaussianNoise::<2>::from_diag_sigmas(0.3, 0.4);
+        let factor = FactorBuilder::new2_unchecked(
+            BetweenResidual::new(VectorVar2::new(1.0, 0.0)),
+            X(0),
+            X(1),
+        )
+        .noise(noise)
+        .build();
+        graph.add_factor(factor);
+
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::new(1.0, 0.0));
+        (graph, values)
+    }
+
+    #[test]
+    fn covariance() {
+        let (graph, values) = chain();
+        let marginals = Marginals::new(&graph, &values).expect("Failed to factor");
+
+        let cov = marginals.covariance(X(0)).expect("Missing X(0)");
+        let cov_exp = Matrix2::new(0.01, 0.0, 0.0, 0.04);
+        assert_matrix_eq!(cov, cov_exp, comp = abs, tol = 1e-6);
+
+        let cov = marginals.covariance(X(1)).expect("Missing X(1)");
+        let cov_exp = Matrix2::new(0.1, 0.0, 0.0, 0.2);
+        assert_matrix_eq!(cov, cov_exp, comp = abs, tol = 1e-6);
+    }
+
+    #[test]
+    fn joint_covariance() {
+        let (graph, values) = chain();
+        let marginals = Marginals::new(&graph, &values).expect("Failed to factor");
+
+        let cov = marginals
+            .joint_covariance(&[X(1).into(), X(0).into()])
+            .expect("Missing keys");
+
+        assert_matrix_eq!(
+            cov.view((0, 0), (2, 2)),
+            Matrix2::new(0.1, 0.0, 0.0, 0.2),
+            comp = abs,
+            tol = 1e-6
+        );
+        assert_matrix_eq!(
+            cov.view((0, 2), (2, 2)),
+            Matrix2::new(0.01, 0.0, 0.0, 0.04),
+            comp = abs,
+            tol = 1e-6
+        );
+        assert_matrix_eq!(cov, cov.transpose(), comp = abs, tol = 1e-6);
+    }
+
+    #[test]
+    fn underconstrained() {
+        let (graph, mut values) = chain();
+        values.insert_unchecked(X(2), VectorVar2::identity());
+        assert!(Marginals::new(&graph, &values).is_none());
+    }
+}
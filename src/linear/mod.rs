@@ -9,5 +9,12 @@ pub use graph::LinearGraph;
 mod values;
 pub use values::LinearValues;
 
+mod marginals;
+pub use marginals::Marginals;
+
 mod solvers;
-pub use solvers::{CholeskySolver, LUSolver, LinearSolver, QRSolver};
+pub use solvers::{
+    AutoParams, AutoSolver, CholeskySolver, DampedParams, DampedSolver, DenseCholeskySolver,
+    DenseQRSolver, FallbackSolver, LUSolver, LinearSolver, LinearSolverError, LinearSolverResult,
+    PcgInfo, PcgParams, PcgSolver, Preconditioner, QRSolver, SchurSolver,
+};
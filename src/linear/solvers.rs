@@ -1,12 +1,62 @@
-use std::ops::Mul;
+use std::{ops::Mul, sync::Arc};
 
 use faer::{
-    prelude::SpSolver,
-    sparse::{linalg::solvers, SparseColMatRef},
-    Mat, MatRef,
+    dyn_stack::{GlobalPodBuffer, PodStack},
+    perm::PermRef,
+    prelude::{SpSolver, SpSolverLstsq},
+    sparse::{
+        linalg::{cholesky, solvers, LuError},
+        FaerError, SparseColMat, SparseColMatRef, SymbolicSparseColMat, SymbolicSparseColMatRef,
+    },
+    Conj, Mat, MatRef,
 };
 
-use crate::dtype;
+use crate::{
+    containers::{DefaultSymbolHandler, ValuesOrder},
+    dtype,
+    linalg::{MatrixX, VectorX},
+};
+
+/// Reasons a linear system couldn't be solved
+///
+/// Where it can be determined, the column of the system that caused the
+/// failure is included. Optimizers use this to find the offending variable, see
+/// [OptError::InvalidSystem](crate::optimizers::OptError::InvalidSystem).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinearSolverError {
+    /// The system isn't positive definite, ie a variable is underconstrained
+    NotPositiveDefinite(Option<usize>),
+    /// The system is singular
+    Singular(Option<usize>),
+    /// The solution contained NaN or infinite values
+    NonFinite,
+    /// An iterative solver didn't reach its tolerance within the maximum number
+    /// of iterations
+    NotConverged,
+    /// The decomposition failed for another reason, such as allocation
+    Failed,
+}
+
+impl LinearSolverError {
+    /// Column of the system that caused the failure, if known
+    pub fn pivot(&self) -> Option<usize> {
+        match self {
+            Self::NotPositiveDefinite(p) | Self::Singular(p) => *p,
+            _ => None,
+        }
+    }
+
+    fn map_pivot(self, f: impl Fn(usize) -> usize) -> Self {
+        match self {
+            Self::NotPositiveDefinite(p) => Self::NotPositiveDefinite(p.map(f)),
+            Self::Singular(p) => Self::Singular(p.map(f)),
+            e => e,
+        }
+    }
+}
+
+/// Result type for linear solvers
+pub type LinearSolverResult = Result<Mat<dtype>, LinearSolverError>;
 
 /// Trait to solve sparse linear systems
 pub trait LinearSolver: Default {
@@ -14,22 +64,109 @@ pub trait LinearSolver: Default {
     ///
     /// This will be used by Cholesky to solve A^T A and by Levenberg-Marquardt
     /// to solve J^T J
-    fn solve_symmetric(&mut self, a: SparseColMatRef<usize, dtype>, b: MatRef<dtype>)
-        -> Mat<dtype>;
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult;
 
     /// Solve a least squares problem
     ///
     /// Used by QR to solve Ax = b, where the number of rows in A is greater
     /// than the number of columns
-    fn solve_lst_sq(&mut self, a: SparseColMatRef<usize, dtype>, b: MatRef<dtype>) -> Mat<dtype>;
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult;
+
+    /// Set the location of each variable in the linear system
+    ///
+    /// Called by the optimizers whenever the ordering is computed. Most solvers
+    /// don't need to know the structure of the problem, so by default this does
+    /// nothing.
+    fn set_order(&mut self, _order: &ValuesOrder) {}
+}
+
+/// Cached symbolic analysis, along with the sparsity pattern it was computed for
+///
+/// The analysis is only reused while the pattern stays exactly the same. Any
+/// change, including appending factors or variables to a warm started
+/// optimizer, redoes the full symbolic analysis.
+struct SymbolicCache<T> {
+    pattern: SymbolicSparseColMat<usize>,
+    symbolic: T,
+}
+
+impl<T: Clone> SymbolicCache<T> {
+    /// Get the cached symbolic analysis, recomputing it if the pattern changed
+    fn get_or_compute(
+        cache: &mut Option<Self>,
+        a: SymbolicSparseColMatRef<usize>,
+        compute: impl FnOnce() -> Result<T, FaerError>,
+    ) -> Result<T, LinearSolverError> {
+        let stale = match cache {
+            Some(c) => {
+                c.pattern.nrows() != a.nrows()
+                    || c.pattern.ncols() != a.ncols()
+                    || c.pattern.col_ptrs() != a.col_ptrs()
+                    || c.pattern.row_indices() != a.row_indices()
+            }
+            None => true,
+        };
+
+        if stale {
+            // Don't keep a stale analysis around if this one fails
+            *cache = None;
+            *cache = Some(Self {
+                pattern: a.to_owned().map_err(|_| LinearSolverError::Failed)?,
+                symbolic: compute().map_err(|_| LinearSolverError::Failed)?,
+            });
+        }
+
+        Ok(cache
+            .as_ref()
+            .expect("Missing symbolic analysis")
+            .symbolic
+            .clone())
+    }
+}
+
+/// Make sure the solution can actually be used
+fn check_finite(x: Mat<dtype>) -> LinearSolverResult {
+    for j in 0..x.ncols() {
+        for i in 0..x.nrows() {
+            if !x.read(i, j).is_finite() {
+                return Err(LinearSolverError::NonFinite);
+            }
+        }
+    }
+    Ok(x)
+}
+
+fn diagonal(a: SparseColMatRef<usize, dtype>, j: usize) -> dtype {
+    a.row_indices_of_col(j)
+        .zip(a.values_of_col(j))
+        .filter(|(i, _)| *i == j)
+        .map(|(_, v)| *v)
+        .sum()
 }
 
 // ------------------------- Cholesky Linear Solver ------------------------- //
 
 /// Cholesky linear solver
+///
+/// The symbolic factorization is cached and reused for as long as the
+/// sparsity pattern of the system doesn't change.
+///
+/// Once [set_order](LinearSolver::set_order) has been called, as all
+/// optimizers in this crate do, the columns are eliminated in the order given,
+/// so the [OrderingStrategy](crate::containers::OrderingStrategy) decides the
+/// fill-in. Otherwise they're reordered using AMD first.
 #[derive(Default)]
 pub struct CholeskySolver {
-    sparsity_pattern: Option<solvers::SymbolicCholesky<usize>>,
+    sparsity_pattern: Option<SymbolicCache<Arc<cholesky::SymbolicCholesky<usize>>>>,
+    ordered: bool,
 }
 
 impl LinearSolver for CholeskySolver {
@@ -37,26 +174,74 @@ impl LinearSolver for CholeskySolver {
         &mut self,
         a: SparseColMatRef<usize, dtype>,
         b: MatRef<dtype>,
-    ) -> Mat<dtype> {
-        if self.sparsity_pattern.is_none() {
-            self.sparsity_pattern = Some(
-                solvers::SymbolicCholesky::try_new(a.symbolic(), faer::Side::Lower)
-                    .expect("Symbolic cholesky failed"),
-            );
-        }
+    ) -> LinearSolverResult {
+        let symbolic =
+            SymbolicCache::get_or_compute(&mut self.sparsity_pattern, a.symbolic(), || {
+                // Identity as a custom permutation, since faer only filters
+                // out the unused triangle of A when permuting
+                let identity = (0..a.ncols()).collect::<Vec<_>>();
+                let ordering = if self.ordered {
+                    cholesky::SymmetricOrdering::Custom(PermRef::new_checked(&identity, &identity))
+                } else {
+                    cholesky::SymmetricOrdering::Amd
+                };
+                cholesky::factorize_symbolic_cholesky(
+                    a.symbolic(),
+                    faer::Side::Lower,
+                    ordering,
+                    Default::default(),
+                )
+                .map(Arc::new)
+            })?;
 
-        solvers::Cholesky::try_new_with_symbolic(
-            self.sparsity_pattern
-                .clone()
-                .expect("Missing symbol cholesky"),
-            a,
-            faer::Side::Lower,
-        )
-        .expect("Cholesky decomp failed")
-        .solve(&b)
+        // Factor using the lower level interface, which reports where it failed
+        let parallelism = faer::get_global_parallelism();
+        let req = symbolic
+            .factorize_numeric_llt_req::<dtype>(parallelism)
+            .and_then(|req| req.try_or(symbolic.solve_in_place_req::<dtype>(b.ncols())?))
+            .map_err(|_| LinearSolverError::Failed)?;
+        let mut buffer = GlobalPodBuffer::new(req);
+        let mut values = vec![0.0; symbolic.len_values()];
+        let llt = symbolic
+            .factorize_numeric_llt::<dtype>(
+                &mut values,
+                a,
+                faer::Side::Lower,
+                Default::default(),
+                parallelism,
+                PodStack::new(&mut buffer),
+            )
+            .map_err(|e| {
+                // Failed column of the permuted system, which faer counts from one
+                // on the simplicial path but from zero on the supernodal one
+                let j = match symbolic.raw() {
+                    cholesky::SymbolicCholeskyRaw::Simplicial(_) => {
+                        e.non_positive_definite_minor.checked_sub(1)
+                    }
+                    cholesky::SymbolicCholeskyRaw::Supernodal(_) => {
+                        Some(e.non_positive_definite_minor)
+                    }
+                };
+                // Mapped back to the original system
+                let j = j.map(|j| symbolic.perm().map_or(j, |p| p.arrays().0[j]));
+                LinearSolverError::NotPositiveDefinite(j)
+            })?;
+
+        let mut x = b.to_owned();
+        llt.solve_in_place_with_conj(
+            Conj::No,
+            x.as_mut(),
+            parallelism,
+            PodStack::new(&mut buffer),
+        );
+        check_finite(x)
     }
 
-    fn solve_lst_sq(&mut self, a: SparseColMatRef<usize, dtype>, b: MatRef<dtype>) -> Mat<dtype> {
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
         let ata = a
             .transpose()
             .to_col_major()
@@ -67,6 +252,14 @@ impl LinearSolver for CholeskySolver {
 
         self.solve_symmetric(ata.as_ref(), atb.as_ref())
     }
+
+    fn set_order(&mut self, _order: &ValuesOrder) {
+        // The cached analysis may have been reordered
+        if !self.ordered {
+            self.ordered = true;
+            self.sparsity_pattern = None;
+        }
+    }
 }
 
 // ------------------------- QR Linear Solver ------------------------- //
@@ -74,7 +267,7 @@ impl LinearSolver for CholeskySolver {
 /// QR linear solver
 #[derive(Default)]
 pub struct QRSolver {
-    sparsity_pattern: Option<solvers::SymbolicQr<usize>>,
+    sparsity_pattern: Option<SymbolicCache<solvers::SymbolicQr<usize>>>,
 }
 
 impl LinearSolver for QRSolver {
@@ -82,26 +275,28 @@ impl LinearSolver for QRSolver {
         &mut self,
         a: SparseColMatRef<usize, dtype>,
         b: MatRef<dtype>,
-    ) -> Mat<dtype> {
+    ) -> LinearSolverResult {
         self.solve_lst_sq(a, b)
     }
 
-    fn solve_lst_sq(&mut self, a: SparseColMatRef<usize, dtype>, b: MatRef<dtype>) -> Mat<dtype> {
-        if self.sparsity_pattern.is_none() {
-            self.sparsity_pattern =
-                Some(solvers::SymbolicQr::try_new(a.symbolic()).expect("Symbolic QR failed"));
-        }
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let symbolic =
+            SymbolicCache::get_or_compute(&mut self.sparsity_pattern, a.symbolic(), || {
+                solvers::SymbolicQr::try_new(a.symbolic())
+            })?;
 
         // TODO: I think we're doing an extra copy here from solution -> slice solution
-        solvers::Qr::try_new_with_symbolic(
-            self.sparsity_pattern.clone().expect("Missing symbolic QR"),
-            a,
-        )
-        .expect("QR failed")
-        .solve(&b)
-        .as_ref()
-        .subrows(0, a.ncols())
-        .to_owned()
+        let x = solvers::Qr::try_new_with_symbolic(symbolic, a)
+            .map_err(|_| LinearSolverError::Failed)?
+            .solve(&b)
+            .as_ref()
+            .subrows(0, a.ncols())
+            .to_owned();
+        check_finite(x)
     }
 }
 
@@ -110,7 +305,7 @@ impl LinearSolver for QRSolver {
 /// LU linear solver
 #[derive(Default)]
 pub struct LUSolver {
-    sparsity_pattern: Option<solvers::SymbolicLu<usize>>,
+    sparsity_pattern: Option<SymbolicCache<solvers::SymbolicLu<usize>>>,
 }
 
 impl LinearSolver for LUSolver {
@@ -118,21 +313,659 @@ impl LinearSolver for LUSolver {
         &mut self,
         a: SparseColMatRef<usize, dtype>,
         b: MatRef<dtype>,
-    ) -> Mat<dtype> {
-        if self.sparsity_pattern.is_none() {
-            self.sparsity_pattern =
-                Some(solvers::SymbolicLu::try_new(a.symbolic()).expect("Symbolic LU failed"));
+    ) -> LinearSolverResult {
+        let symbolic =
+            SymbolicCache::get_or_compute(&mut self.sparsity_pattern, a.symbolic(), || {
+                solvers::SymbolicLu::try_new(a.symbolic())
+            })?;
+
+        let lu = solvers::Lu::try_new_with_symbolic(symbolic, a.as_ref()).map_err(|e| match e {
+            LuError::Generic(_) => LinearSolverError::Failed,
+            LuError::SymbolicSingular(i) => LinearSolverError::Singular(Some(i)),
+        })?;
+        check_finite(lu.solve(&b))
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let ata = a
+            .transpose()
+            .to_col_major()
+            .expect("Failed to transpose A matrix")
+            .mul(a);
+        let atb = a.transpose().mul(b);
+
+        self.solve_symmetric(ata.as_ref(), atb.as_ref())
+    }
+}
+
+// ------------------------- Dense Linear Solvers ------------------------- //
+
+/// Dense Cholesky linear solver
+///
+/// Copies the system into a dense matrix before factoring it. This skips the
+/// symbolic analysis of the sparse solvers, which dominates for small problems
+/// of a few dozen variables such as calibration, but scales cubically with the
+/// size of the problem. See [AutoSolver] to pick between this and
+/// [CholeskySolver] automatically.
+#[derive(Default)]
+pub struct DenseCholeskySolver;
+
+impl DenseCholeskySolver {
+    fn solve_dense(a: MatRef<dtype>, b: MatRef<dtype>) -> LinearSolverResult {
+        // Despite the name, the minor is the column where the factorization failed
+        let cholesky = a.cholesky(faer::Side::Lower).map_err(|e| {
+            LinearSolverError::NotPositiveDefinite(Some(e.non_positive_definite_minor))
+        })?;
+        check_finite(cholesky.solve(b))
+    }
+}
+
+impl LinearSolver for DenseCholeskySolver {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        Self::solve_dense(a.to_dense().as_ref(), b)
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let a = a.to_dense();
+        let ata = a.transpose() * &a;
+        let atb = a.transpose() * b;
+
+        Self::solve_dense(ata.as_ref(), atb.as_ref())
+    }
+}
+
+/// Dense QR linear solver
+///
+/// Like [DenseCholeskySolver], but factors the Jacobian itself rather than the
+/// normal equations, which is more robust for poorly conditioned problems.
+#[derive(Default)]
+pub struct DenseQRSolver;
+
+impl LinearSolver for DenseQRSolver {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        self.solve_lst_sq(a, b)
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        check_finite(a.to_dense().qr().solve_lstsq(b))
+    }
+}
+
+// ------------------------- Automatic Linear Solver ------------------------- //
+
+/// Parameters for [AutoSolver]
+#[derive(Debug, Clone)]
+pub struct AutoParams {
+    /// Systems with at most this many columns always use the dense solver
+    pub dense_dim: usize,
+    /// Systems with more than this many columns always use the sparse solver
+    pub dense_dim_max: usize,
+    /// In between, the dense solver is used if at least this fraction of
+    /// entries are nonzero
+    pub dense_density: dtype,
+}
+
+impl Default for AutoParams {
+    fn default() -> Self {
+        Self {
+            dense_dim: 100,
+            dense_dim_max: 1000,
+            dense_density: 0.1,
         }
+    }
+}
 
-        solvers::Lu::try_new_with_symbolic(
-            self.sparsity_pattern.clone().expect("Symbolic LU missing"),
-            a.as_ref(),
-        )
-        .expect("LU decomp failed")
-        .solve(&b)
+/// Linear solver that picks a dense or sparse solver based on the system
+///
+/// Small or dense systems are solved using the dense solver, defaulting to
+/// [DenseCholeskySolver], and everything else using the sparse solver,
+/// defaulting to [CholeskySolver]. The choice is made for every solve, so it
+/// follows a problem as it grows.
+/// ```
+/// # use factrs::{containers::Graph, linear::AutoSolver, optimizers::LevenMarquardt};
+/// # let graph = Graph::new();
+/// let optimizer: LevenMarquardt<AutoSolver> = LevenMarquardt::new(graph);
+/// ```
+#[derive(Default)]
+pub struct AutoSolver<D: LinearSolver = DenseCholeskySolver, S: LinearSolver = CholeskySolver> {
+    /// Parameters for picking the solver
+    pub params: AutoParams,
+    dense: D,
+    sparse: S,
+    used_dense: Option<bool>,
+}
+
+impl<D: LinearSolver, S: LinearSolver> AutoSolver<D, S> {
+    pub fn new(params: AutoParams) -> Self {
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the last solve used the dense solver, None if nothing has been
+    /// solved yet
+    pub fn used_dense(&self) -> Option<bool> {
+        self.used_dense
+    }
+
+    fn use_dense(&mut self, a: SparseColMatRef<usize, dtype>) -> bool {
+        let n = a.ncols();
+        let size = (a.nrows() * a.ncols()).max(1);
+        let density = a.compute_nnz() as dtype / size as dtype;
+        let dense = n <= self.params.dense_dim
+            || (n <= self.params.dense_dim_max && density >= self.params.dense_density);
+        self.used_dense = Some(dense);
+        dense
+    }
+}
+
+impl<D: LinearSolver, S: LinearSolver> LinearSolver for AutoSolver<D, S> {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        if self.use_dense(a) {
+            self.dense.solve_symmetric(a, b)
+        } else {
+            self.sparse.solve_symmetric(a, b)
+        }
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        if self.use_dense(a) {
+            self.dense.solve_lst_sq(a, b)
+        } else {
+            self.sparse.solve_lst_sq(a, b)
+        }
+    }
+
+    fn set_order(&mut self, order: &ValuesOrder) {
+        self.dense.set_order(order);
+        self.sparse.set_order(order);
+    }
+}
+
+// ------------------------- Schur Complement Linear Solver ------------------------- //
+
+/// Schur complement linear solver
+///
+/// Meant for problems like bundle adjustment, where most variables (ie
+/// landmarks) only connect to a handful of others (ie poses). Variables with
+/// the given symbols are eliminated first, which is cheap since their block of
+/// the system is block diagonal. Only the remaining reduced system is factored
+/// using [CholeskySolver], after which the eliminated variables are recovered
+/// by back-substitution.
+///
+/// Eliminated variables that share a factor with another eliminated variable
+/// are left in the reduced system. Relies on [set_order](LinearSolver::set_order)
+/// to find the variables, which all optimizers in this crate call. If the
+/// system doesn't match the last order set, a warning is logged and it's
+/// solved directly with [CholeskySolver].
+///
+/// ```
+/// # use factrs::{containers::Graph, linear::SchurSolver, optimizers::GaussNewton};
+/// # let graph = Graph::new();
+/// let solver = SchurSolver::new(['L']);
+/// let optimizer = GaussNewton::with_solver(graph, solver);
+/// ```
+#[derive(Default)]
+pub struct SchurSolver {
+    symbols: Vec<char>,
+    // Location of each variable that can be eliminated
+    blocks: Vec<(usize, usize)>,
+    dim: usize,
+    reduced: CholeskySolver,
+}
+
+/// An eliminated block, kept around for back-substitution
+struct Eliminated {
+    idx: usize,
+    touched: Vec<usize>,
+    h_cl: MatrixX,
+    h_ll_inv: MatrixX,
+    b_l: MatrixX,
+}
+
+impl SchurSolver {
+    /// Create a solver that eliminates all variables with these symbols first
+    pub fn new(symbols: impl IntoIterator<Item = char>) -> Self {
+        Self {
+            symbols: symbols.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl LinearSolver for SchurSolver {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let n = a.ncols();
+        let m = b.ncols();
+
+        // Find the block each column belongs to
+        let mut block_of = vec![None; n];
+        if self.dim == n {
+            for (bi, (idx, dim)) in self.blocks.iter().enumerate() {
+                block_of[*idx..*idx + *dim].fill(Some(bi));
+            }
+        } else {
+            log::warn!(
+                "SchurSolver was set up for a system of size {} but got {}, nothing will be eliminated. Was set_order called?",
+                self.dim,
+                n
+            );
+        }
+
+        // Blocks coupled to each other can't be eliminated independently
+        let mut coupled = vec![false; self.blocks.len()];
+        for (j, bj) in block_of.iter().enumerate() {
+            if let Some(bj) = bj {
+                for i in a.row_indices_of_col(j) {
+                    if let Some(bi) = block_of[i] {
+                        if bi != *bj {
+                            coupled[bi] = true;
+                            coupled[*bj] = true;
+                        }
+                    }
+                }
+            }
+        }
+        for bi in block_of.iter_mut() {
+            if bi.is_some_and(|bi| coupled[bi]) {
+                *bi = None;
+            }
+        }
+
+        // Location of each remaining column in the reduced system
+        let mut reduced_idx = vec![None; n];
+        let mut full_idx = Vec::new();
+        for (j, bj) in block_of.iter().enumerate() {
+            if bj.is_none() {
+                reduced_idx[j] = Some(full_idx.len());
+                full_idx.push(j);
+            }
+        }
+        let dim_reduced = full_idx.len();
+
+        // Start from the reduced block of A and b
+        let mut triplets = Vec::new();
+        let mut rhs = Mat::<dtype>::zeros(dim_reduced, m);
+        for j in 0..n {
+            if let Some(rj) = reduced_idx[j] {
+                for (i, v) in a.row_indices_of_col(j).zip(a.values_of_col(j)) {
+                    if let Some(ri) = reduced_idx[i] {
+                        triplets.push((ri, rj, *v));
+                    }
+                }
+                for c in 0..m {
+                    rhs.write(rj, c, b.read(j, c));
+                }
+            }
+        }
+
+        // Eliminate each block, adding its Schur complement to the reduced system
+        let mut eliminated = Vec::new();
+        for (bi, &(idx, dim)) in self.blocks.iter().enumerate() {
+            if coupled[bi] || block_of.get(idx) != Some(&Some(bi)) {
+                continue;
+            }
+
+            let mut h_ll = MatrixX::zeros(dim, dim);
+            let mut entries = Vec::new();
+            for k in 0..dim {
+                let col = a.row_indices_of_col(idx + k).zip(a.values_of_col(idx + k));
+                for (i, v) in col {
+                    match reduced_idx[i] {
+                        Some(ri) => entries.push((ri, k, *v)),
+                        None => h_ll[(i - idx, k)] += v,
+                    }
+                }
+            }
+
+            let mut touched = entries.iter().map(|e| e.0).collect::<Vec<_>>();
+            touched.sort_unstable();
+            touched.dedup();
+            let mut h_cl = MatrixX::zeros(touched.len(), dim);
+            for (ri, k, v) in entries {
+                let p = touched.binary_search(&ri).expect("Missing touched index");
+                h_cl[(p, k)] += v;
+            }
+
+            let h_ll_inv = h_ll
+                .cholesky()
+                .ok_or(LinearSolverError::NotPositiveDefinite(Some(idx)))?
+                .inverse();
+            let b_l = MatrixX::from_fn(dim, m, |i, c| b.read(idx + i, c));
+            let w = &h_cl * &h_ll_inv;
+            let s = &w * h_cl.transpose();
+            let r = &w * &b_l;
+            for (p, rp) in touched.iter().enumerate() {
+                for (q, rq) in touched.iter().enumerate() {
+                    triplets.push((*rp, *rq, -s[(p, q)]));
+                }
+                for c in 0..m {
+                    rhs.write(*rp, c, rhs.read(*rp, c) - r[(p, c)]);
+                }
+            }
+
+            eliminated.push(Eliminated {
+                idx,
+                touched,
+                h_cl,
+                h_ll_inv,
+                b_l,
+            });
+        }
+
+        // Solve the reduced system
+        let x_c = if dim_reduced > 0 {
+            let s = SparseColMat::try_new_from_triplets(dim_reduced, dim_reduced, &triplets)
+                .expect("Failed to make reduced system");
+            self.reduced
+                .solve_symmetric(s.as_ref(), rhs.as_ref())
+                .map_err(|e| e.map_pivot(|p| full_idx[p]))?
+        } else {
+            Mat::zeros(0, m)
+        };
+
+        // Back-substitute for the eliminated variables
+        let mut x = Mat::<dtype>::zeros(n, m);
+        for (j, rj) in reduced_idx.iter().enumerate() {
+            if let Some(rj) = rj {
+                for c in 0..m {
+                    x.write(j, c, x_c.read(*rj, c));
+                }
+            }
+        }
+        for e in eliminated {
+            let x_t = MatrixX::from_fn(e.touched.len(), m, |p, c| x_c.read(e.touched[p], c));
+            let x_l = e.h_ll_inv * (e.b_l - e.h_cl.transpose() * x_t);
+            for i in 0..x_l.nrows() {
+                for c in 0..m {
+                    x.write(e.idx + i, c, x_l[(i, c)]);
+                }
+            }
+        }
+
+        check_finite(x)
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let ata = a
+            .transpose()
+            .to_col_major()
+            .expect("Failed to transpose A matrix")
+            .mul(a);
+        let atb = a.transpose().mul(b);
+
+        self.solve_symmetric(ata.as_ref(), atb.as_ref())
+    }
+
+    fn set_order(&mut self, order: &ValuesOrder) {
+        self.blocks = order
+            .iter()
+            .filter(|(k, _)| {
+                let (chr, _) = DefaultSymbolHandler::key_to_sym(**k);
+                self.symbols.contains(&chr)
+            })
+            .map(|(_, idx)| (idx.idx, idx.dim))
+            .collect();
+        self.blocks.sort_unstable();
+        self.dim = order.dim();
+        // Remaining variables keep their relative order in the reduced system
+        self.reduced.set_order(order);
+    }
+}
+
+// ------------------------- Conjugate Gradient Linear Solver ------------------------- //
+
+/// Preconditioner used by [PcgSolver]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preconditioner {
+    /// Inverse of the diagonal
+    Jacobi,
+    /// Inverse of the diagonal block of each variable
+    #[default]
+    BlockJacobi,
+}
+
+/// Parameters for [PcgSolver]
+#[derive(Debug, Clone)]
+pub struct PcgParams {
+    /// Converged once the residual norm is below this times the norm of b
+    pub tol: dtype,
+    /// Maximum number of iterations for each solve
+    pub max_iterations: usize,
+    /// Preconditioner to use
+    pub preconditioner: Preconditioner,
+}
+
+impl Default for PcgParams {
+    fn default() -> Self {
+        Self {
+            tol: dtype::EPSILON.sqrt(),
+            max_iterations: 1000,
+            preconditioner: Preconditioner::default(),
+        }
+    }
+}
+
+/// Summary of the last solve of a [PcgSolver]
+#[derive(Debug, Clone)]
+pub struct PcgInfo {
+    /// Number of iterations taken
+    pub iterations: usize,
+    /// Relative residual norm reached
+    pub residual: dtype,
+    /// Whether the tolerance was reached
+    pub converged: bool,
+}
+
+/// Preconditioned conjugate gradient linear solver
+///
+/// An iterative solver that only needs matrix-vector products, so no
+/// factorization is ever formed and there's no fill-in. Useful for very large
+/// problems where direct solvers run out of memory. The block-Jacobi
+/// preconditioner uses one block per variable, found using
+/// [set_order](LinearSolver::set_order), and falls back to Jacobi if it hasn't
+/// been called.
+///
+/// If the tolerance isn't reached within the maximum number of iterations,
+/// [NotConverged](LinearSolverError::NotConverged) is returned, so it can be
+/// paired with a direct solver using [FallbackSolver]. Details of the last
+/// solve can be found using [info](PcgSolver::info).
+#[derive(Default)]
+pub struct PcgSolver {
+    /// Parameters for the solver
+    pub params: PcgParams,
+    blocks: Vec<(usize, usize)>,
+    dim: usize,
+    info: Option<PcgInfo>,
+}
+
+impl PcgSolver {
+    pub fn new(params: PcgParams) -> Self {
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+
+    /// Summary of the last solve, None if nothing has been solved yet
+    pub fn info(&self) -> Option<&PcgInfo> {
+        self.info.as_ref()
+    }
+
+    /// Inverse of each diagonal block of A
+    fn preconditioner(&self, a: SparseColMatRef<usize, dtype>) -> Vec<(usize, MatrixX)> {
+        let n = a.ncols();
+        let blocks = match self.params.preconditioner {
+            Preconditioner::BlockJacobi if self.dim == n => self.blocks.clone(),
+            _ => (0..n).map(|i| (i, 1)).collect(),
+        };
+
+        blocks
+            .into_iter()
+            .map(|(idx, dim)| {
+                let mut block = MatrixX::zeros(dim, dim);
+                for k in 0..dim {
+                    let col = a.row_indices_of_col(idx + k).zip(a.values_of_col(idx + k));
+                    for (i, v) in col {
+                        if i >= idx && i < idx + dim {
+                            block[(i - idx, k)] += v;
+                        }
+                    }
+                }
+                // Fall back to the diagonal if the block isn't invertible
+                let inv = block
+                    .clone()
+                    .cholesky()
+                    .map(|c| c.inverse())
+                    .unwrap_or_else(|| {
+                        MatrixX::from_diagonal(&block.diagonal().map(|d| {
+                            if d > 0.0 {
+                                1.0 / d
+                            } else {
+                                1.0
+                            }
+                        }))
+                    });
+                (idx, inv)
+            })
+            .collect()
+    }
+}
+
+impl LinearSolver for PcgSolver {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let n = a.ncols();
+        let m_inv = self.preconditioner(a);
+        let apply_m_inv = |r: &VectorX| {
+            let mut z = VectorX::zeros(n);
+            for (idx, inv) in m_inv.iter() {
+                z.rows_mut(*idx, inv.nrows())
+                    .copy_from(&(inv * r.rows(*idx, inv.nrows())));
+            }
+            z
+        };
+        let mul_a = |x: &VectorX| {
+            let mut y = VectorX::zeros(n);
+            for j in 0..n {
+                for (i, v) in a.row_indices_of_col(j).zip(a.values_of_col(j)) {
+                    y[i] += v * x[j];
+                }
+            }
+            y
+        };
+
+        let mut out = Mat::<dtype>::zeros(n, b.ncols());
+        let mut info = PcgInfo {
+            iterations: 0,
+            residual: 0.0,
+            converged: true,
+        };
+        for c in 0..b.ncols() {
+            let b = VectorX::from_fn(n, |i, _| b.read(i, c));
+            let b_norm = b.norm();
+            let mut x = VectorX::zeros(n);
+            let mut residual = 0.0;
+            let mut converged = b_norm == 0.0;
+            let mut iterations = 0;
+
+            let mut r = b.clone();
+            let mut z = apply_m_inv(&r);
+            let mut p = z.clone();
+            let mut rz = r.dot(&z);
+            while !converged && iterations < self.params.max_iterations {
+                iterations += 1;
+                let ap = mul_a(&p);
+                let pap = p.dot(&ap);
+                if pap <= 0.0 || pap.is_nan() {
+                    // Matrix isn't positive definite along p
+                    return Err(LinearSolverError::NotPositiveDefinite(None));
+                }
+                let alpha = rz / pap;
+                x.axpy(alpha, &p, 1.0);
+                r.axpy(-alpha, &ap, 1.0);
+
+                residual = r.norm() / b_norm;
+                if residual <= self.params.tol {
+                    converged = true;
+                    break;
+                }
+
+                z = apply_m_inv(&r);
+                let rz_new = r.dot(&z);
+                p = &z + &p * (rz_new / rz);
+                rz = rz_new;
+            }
+
+            for i in 0..n {
+                out.write(i, c, x[i]);
+            }
+            info.iterations = info.iterations.max(iterations);
+            info.residual = info.residual.max(residual);
+            info.converged &= converged;
+        }
+
+        let converged = info.converged;
+        if !converged {
+            log::warn!(
+                "PCG failed to converge after {} iterations, relative residual {:.3e}",
+                info.iterations,
+                info.residual
+            );
+        }
+        self.info = Some(info);
+
+        if !converged {
+            return Err(LinearSolverError::NotConverged);
+        }
+        check_finite(out)
     }
 
-    fn solve_lst_sq(&mut self, a: SparseColMatRef<usize, dtype>, b: MatRef<dtype>) -> Mat<dtype> {
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
         let ata = a
             .transpose()
             .to_col_major()
@@ -142,6 +975,182 @@ impl LinearSolver for LUSolver {
 
         self.solve_symmetric(ata.as_ref(), atb.as_ref())
     }
+
+    fn set_order(&mut self, order: &ValuesOrder) {
+        self.blocks = order.iter().map(|(_, idx)| (idx.idx, idx.dim)).collect();
+        self.blocks.sort_unstable();
+        self.dim = order.dim();
+    }
+}
+
+// ------------------------- Fallback Linear Solver ------------------------- //
+
+/// Linear solver that falls back to a second solver when the first fails
+///
+/// Solvers are tried in order, and the first success is returned. Chains of
+/// more than two solvers can be made by nesting, for example to try Cholesky,
+/// then QR, and finally a damped Cholesky,
+/// ```
+/// # use factrs::{
+/// #    containers::Graph,
+/// #    linear::{CholeskySolver, DampedSolver, FallbackSolver, QRSolver},
+/// #    optimizers::GaussNewton,
+/// # };
+/// # let graph = Graph::new();
+/// type Solver = FallbackSolver<CholeskySolver, FallbackSolver<QRSolver, DampedSolver>>;
+/// let optimizer: GaussNewton<Solver> = GaussNewton::new(graph);
+/// ```
+#[derive(Default)]
+pub struct FallbackSolver<P: LinearSolver = CholeskySolver, F: LinearSolver = QRSolver> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P: LinearSolver, F: LinearSolver> FallbackSolver<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<P: LinearSolver, F: LinearSolver> LinearSolver for FallbackSolver<P, F> {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        self.primary.solve_symmetric(a, b).or_else(|e| {
+            log::warn!("Linear solver failed with {:?}, falling back", e);
+            self.fallback.solve_symmetric(a, b)
+        })
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        self.primary.solve_lst_sq(a, b).or_else(|e| {
+            log::warn!("Linear solver failed with {:?}, falling back", e);
+            self.fallback.solve_lst_sq(a, b)
+        })
+    }
+
+    fn set_order(&mut self, order: &ValuesOrder) {
+        self.primary.set_order(order);
+        self.fallback.set_order(order);
+    }
+}
+
+// ------------------------- Damped Linear Solver ------------------------- //
+
+/// Parameters for [DampedSolver]
+#[derive(Debug, Clone)]
+pub struct DampedParams {
+    /// Damping used on the first retry
+    pub lambda_init: dtype,
+    /// Factor to increase the damping by on each retry
+    pub lambda_factor: dtype,
+    /// Give up once the damping passes this
+    pub lambda_max: dtype,
+}
+
+impl Default for DampedParams {
+    fn default() -> Self {
+        Self {
+            lambda_init: 1e-6,
+            lambda_factor: 10.0,
+            lambda_max: 1e3,
+        }
+    }
+}
+
+/// Linear solver that adds damping until the system can be solved
+///
+/// If the wrapped solver fails, $\lambda \max(A_{ii}, 1)$ is added to each
+/// diagonal entry of the symmetric system, with $\lambda$ increased each time
+/// it fails again. This regularizes underconstrained variables towards zero
+/// change, similar to a step of Levenberg-Marquardt, and is meant to be used
+/// as the last resort of a [FallbackSolver]. The damping of the last solve can
+/// be found using [lambda](DampedSolver::lambda).
+#[derive(Default)]
+pub struct DampedSolver<S: LinearSolver = CholeskySolver> {
+    /// Parameters for the damping
+    pub params: DampedParams,
+    solver: S,
+    lambda: Option<dtype>,
+}
+
+impl<S: LinearSolver> DampedSolver<S> {
+    pub fn new(params: DampedParams) -> Self {
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+
+    /// Damping added in the last solve, None if none was needed
+    pub fn lambda(&self) -> Option<dtype> {
+        self.lambda
+    }
+}
+
+impl<S: LinearSolver> LinearSolver for DampedSolver<S> {
+    fn solve_symmetric(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        self.lambda = None;
+        let mut result = self.solver.solve_symmetric(a, b);
+
+        let mut lambda = self.params.lambda_init;
+        while let Err(e) = result {
+            if lambda > self.params.lambda_max {
+                return Err(e);
+            }
+            log::warn!(
+                "Linear solver failed with {:?}, damping by {:.3e}",
+                e,
+                lambda
+            );
+
+            let mut triplets = (0..a.ncols())
+                .map(|j| (j, j, lambda * diagonal(a, j).max(1.0)))
+                .collect::<Vec<_>>();
+            for j in 0..a.ncols() {
+                for (i, v) in a.row_indices_of_col(j).zip(a.values_of_col(j)) {
+                    triplets.push((i, j, *v));
+                }
+            }
+            let damped = SparseColMat::try_new_from_triplets(a.nrows(), a.ncols(), &triplets)
+                .expect("Failed to make damped system");
+
+            result = self.solver.solve_symmetric(damped.as_ref(), b);
+            self.lambda = Some(lambda);
+            lambda *= self.params.lambda_factor;
+        }
+
+        result
+    }
+
+    fn solve_lst_sq(
+        &mut self,
+        a: SparseColMatRef<usize, dtype>,
+        b: MatRef<dtype>,
+    ) -> LinearSolverResult {
+        let ata = a
+            .transpose()
+            .to_col_major()
+            .expect("Failed to transpose A matrix")
+            .mul(a);
+        let atb = a.transpose().mul(b);
+
+        self.solve_symmetric(ata.as_ref(), atb.as_ref())
+    }
+
+    fn set_order(&mut self, order: &ValuesOrder) {
+        self.solver.set_order(order);
+    }
 }
 
 #[cfg(test)]
@@ -150,6 +1159,18 @@ mod test {
     use matrixcompare::assert_matrix_eq;
 
     use super::*;
+    use crate::{
+        containers::{FactorBuilder, Graph, Key, OrderingStrategy, Values},
+        linalg::DiffResult,
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::{L, X},
+        variables::{Variable, VectorVar2},
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
 
     fn solve<T: LinearSolver>(solver: &mut T) {
         let a = SparseColMat::<usize, dtype>::try_new_from_triplets(
@@ -168,18 +1189,171 @@ mod test {
         let b = mat![[15.0], [-3.0], [33.0]];
 
         let x_exp = mat![[1.874901], [-0.566112]];
-        let x = solver.solve_lst_sq(a.as_ref(), b.as_ref());
+        let x = solver
+            .solve_lst_sq(a.as_ref(), b.as_ref())
+            .expect("Solve failed");
         println!("{:?}", x);
 
         assert_matrix_eq!(x, x_exp, comp = abs, tol = 1e-6);
     }
 
+    // Solve a differently structured system with the same solver
+    fn resolve<T: LinearSolver>(solver: &mut T) {
+        solve(solver);
+
+        let a =
+            SparseColMat::<usize, dtype>::try_new_from_triplets(2, 1, &[(0, 0, 2.0), (1, 0, 2.0)])
+                .expect("Failed to make symbolic matrix");
+        let b = mat![[2.0], [4.0]];
+
+        let x = solver
+            .solve_lst_sq(a.as_ref(), b.as_ref())
+            .expect("Solve failed");
+        assert_matrix_eq!(x, mat![[1.5]], comp = abs, tol = 1e-6);
+    }
+
     #[test]
     fn test_cholesky_solver() {
         let mut solver = CholeskySolver::default();
         solve(&mut solver);
     }
 
+    #[test]
+    fn test_cholesky_resolve() {
+        let mut solver = CholeskySolver::default();
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_qr_resolve() {
+        let mut solver = QRSolver::default();
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_lu_resolve() {
+        let mut solver = LUSolver::default();
+        resolve(&mut solver);
+    }
+
+    // Landmarks L only connect to poses X, except for L(2) and L(3)
+    fn schur_system() -> (SparseColMat<usize, dtype>, Mat<dtype>, ValuesOrder) {
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::identity());
+        for i in 0..4 {
+            values.insert_unchecked(L(i), VectorVar2::identity());
+        }
+        let order = ValuesOrder::from_values(&values);
+
+        let mut j = Vec::new();
+        let mut row = 0;
+        let mut add = |k1: Key, k2: Key, s: dtype| {
+            for d in 0..2 {
+                j.push((
+                    row + d,
+                    order.get(k1).expect("Missing key").idx + d,
+                    1.0 + s,
+                ));
+                j.push((row + d, order.get(k2).expect("Missing key").idx + d, -s));
+            }
+            row += 2;
+        };
+        add(X(0).into(), X(1).into(), 0.5);
+        for i in 0..4 {
+            add(X(0).into(), L(i).into(), 0.1 * i as dtype);
+            add(X(1).into(), L(i).into(), 0.2);
+        }
+        add(L(2).into(), L(3).into(), 0.3);
+
+        let j = SparseColMat::<usize, dtype>::try_new_from_triplets(row, order.dim(), &j)
+            .expect("Failed to make jacobian");
+        let b = Mat::from_fn(row, 1, |i, _| i as dtype * 0.3 - 1.0);
+        (j, b, order)
+    }
+
+    #[test]
+    fn test_schur_solver() {
+        let mut solver = SchurSolver::default();
+        solve(&mut solver);
+    }
+
+    #[test]
+    fn test_schur_resolve() {
+        let mut solver = SchurSolver::default();
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_schur_eliminate() {
+        let (j, b, order) = schur_system();
+        let x_exp = CholeskySolver::default()
+            .solve_lst_sq(j.as_ref(), b.as_ref())
+            .expect("Solve failed");
+
+        let mut solver = SchurSolver::new(['L']);
+        solver.set_order(&order);
+        let x = solver
+            .solve_lst_sq(j.as_ref(), b.as_ref())
+            .expect("Solve failed");
+        assert_matrix_eq!(x, x_exp, comp = abs, tol = TOL);
+
+        // Pattern is reused on the second solve
+        let x = solver
+            .solve_lst_sq(j.as_ref(), b.as_ref())
+            .expect("Solve failed");
+        assert_matrix_eq!(x, x_exp, comp = abs, tol = TOL);
+    }
+
+    #[test]
+    fn test_pcg_solver() {
+        let mut solver = PcgSolver::default();
+        solve(&mut solver);
+        assert!(solver.info().expect("Missing info").converged);
+    }
+
+    #[test]
+    fn test_pcg_resolve() {
+        let mut solver = PcgSolver::default();
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_pcg_block_jacobi() {
+        let (j, b, order) = schur_system();
+        let x_exp = CholeskySolver::default()
+            .solve_lst_sq(j.as_ref(), b.as_ref())
+            .expect("Solve failed");
+
+        for preconditioner in [Preconditioner::Jacobi, Preconditioner::BlockJacobi] {
+            let mut solver = PcgSolver::new(PcgParams {
+                preconditioner,
+                ..Default::default()
+            });
+            solver.set_order(&order);
+            let x = solver
+                .solve_lst_sq(j.as_ref(), b.as_ref())
+                .expect("Solve failed");
+            assert_matrix_eq!(x, x_exp, comp = abs, tol = TOL);
+        }
+    }
+
+    #[test]
+    fn test_pcg_not_converged() {
+        let (j, b, order) = schur_system();
+        let mut solver = PcgSolver::new(PcgParams {
+            max_iterations: 1,
+            ..Default::default()
+        });
+        solver.set_order(&order);
+        let result = solver.solve_lst_sq(j.as_ref(), b.as_ref());
+        assert_eq!(result, Err(LinearSolverError::NotConverged));
+
+        let info = solver.info().expect("Missing info");
+        assert!(!info.converged);
+        assert_eq!(info.iterations, 1);
+    }
+
     #[test]
     fn test_qr_solver() {
         let mut solver = QRSolver::default();
@@ -191,4 +1365,189 @@ mod test {
         let mut solver = LUSolver::default();
         solve(&mut solver);
     }
+
+    #[test]
+    fn test_dense_cholesky_solver() {
+        let mut solver = DenseCholeskySolver;
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_dense_qr_solver() {
+        let mut solver = DenseQRSolver;
+        resolve(&mut solver);
+    }
+
+    #[test]
+    fn test_auto_solver() {
+        let mut solver = AutoSolver::<DenseCholeskySolver, CholeskySolver>::default();
+        resolve(&mut solver);
+        assert_eq!(solver.used_dense(), Some(true));
+
+        // Large and tridiagonal
+        let n = 200;
+        let mut triplets = Vec::new();
+        for i in 0..n {
+            triplets.push((i, i, 4.0));
+            if i + 1 < n {
+                triplets.push((i, i + 1, -1.0));
+                triplets.push((i + 1, i, -1.0));
+            }
+        }
+        let a = SparseColMat::<usize, dtype>::try_new_from_triplets(n, n, &triplets)
+            .expect("Failed to make symbolic matrix");
+        let b = Mat::from_fn(n, 1, |i, _| i as dtype / n as dtype);
+        let x = solver
+            .solve_symmetric(a.as_ref(), b.as_ref())
+            .expect("Solve failed");
+        assert_eq!(solver.used_dense(), Some(false));
+
+        let x_exp = DenseCholeskySolver
+            .solve_symmetric(a.as_ref(), b.as_ref())
+            .expect("Solve failed");
+        assert_matrix_eq!(x, x_exp, comp = abs, tol = TOL);
+    }
+
+    // Second variable is unconstrained
+    fn singular_system() -> (SparseColMat<usize, dtype>, Mat<dtype>) {
+        let a =
+            SparseColMat::<usize, dtype>::try_new_from_triplets(2, 2, &[(0, 0, 2.0), (1, 1, 0.0)])
+                .expect("Failed to make symbolic matrix");
+        (a, mat![[4.0], [0.0]])
+    }
+
+    #[test]
+    fn test_cholesky_singular() {
+        let (a, b) = singular_system();
+        let result = CholeskySolver::default().solve_symmetric(a.as_ref(), b.as_ref());
+        assert_eq!(result, Err(LinearSolverError::NotPositiveDefinite(Some(1))));
+
+        let result = DenseCholeskySolver.solve_symmetric(a.as_ref(), b.as_ref());
+        assert_eq!(result, Err(LinearSolverError::NotPositiveDefinite(Some(1))));
+    }
+
+    #[test]
+    fn test_cholesky_indefinite() {
+        // Every diagonal is positive, but eliminating the outer variables
+        // leaves nothing for the middle one
+        let a = SparseColMat::<usize, dtype>::try_new_from_triplets(
+            3,
+            3,
+            &[
+                (0, 0, 1.0),
+                (1, 1, 1.0),
+                (2, 2, 1.0),
+                (0, 2, 1.0),
+                (2, 0, 1.0),
+                (1, 2, 1.0),
+                (2, 1, 1.0),
+            ],
+        )
+        .expect("Failed to make symbolic matrix");
+        let b = mat![[1.0], [1.0], [1.0]];
+        let result = CholeskySolver::default().solve_symmetric(a.as_ref(), b.as_ref());
+        assert_eq!(result, Err(LinearSolverError::NotPositiveDefinite(Some(2))));
+    }
+
+    #[test]
+    fn test_cholesky_indefinite_supernodal() {
+        // Dense enough that faer factors it with supernodes
+        let n = 300;
+        let system = |negative: &dyn Fn(usize) -> bool| {
+            let mut triplets = Vec::new();
+            for j in 0..n {
+                for i in 0..n {
+                    let diag = if negative(i) { -1.0 } else { 1.0 };
+                    triplets.push((i, j, if i == j { diag } else { 1e-3 }));
+                }
+            }
+            SparseColMat::<usize, dtype>::try_new_from_triplets(n, n, &triplets)
+                .expect("Failed to make symbolic matrix")
+        };
+        let b = Mat::<dtype>::from_fn(n, 1, |_, _| 1.0);
+        let a = system(&|_| false);
+        let symbolic = cholesky::factorize_symbolic_cholesky(
+            a.symbolic(),
+            faer::Side::Lower,
+            Default::default(),
+            Default::default(),
+        )
+        .expect("Symbolic cholesky failed");
+        assert!(matches!(
+            symbolic.raw(),
+            cholesky::SymbolicCholeskyRaw::Supernodal(_)
+        ));
+
+        // A single bad column is found wherever it is
+        for k in [0, 1, 150, n - 1] {
+            let a = system(&|i| i == k);
+            let result = CholeskySolver::default().solve_symmetric(a.as_ref(), b.as_ref());
+            assert_eq!(result, Err(LinearSolverError::NotPositiveDefinite(Some(k))));
+        }
+
+        // Including when the very first pivot fails
+        let a = system(&|_| true);
+        let result = CholeskySolver::default().solve_symmetric(a.as_ref(), b.as_ref());
+        assert!(matches!(
+            result,
+            Err(LinearSolverError::NotPositiveDefinite(Some(j))) if j < n
+        ));
+    }
+
+    #[test]
+    fn test_cholesky_follows_order() {
+        // Star graph, where eliminating the center first fills in everything
+        let n = 6;
+        let mut graph = Graph::new();
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        let res = PriorResidual::new(VectorVar2::identity());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        for i in 1..=n {
+            values.insert_unchecked(X(i), VectorVar2::identity());
+            let res = BetweenResidual::new(VectorVar2::new(1.0, 0.0));
+            graph.add_factor(FactorBuilder::new2_unchecked(res, X(0), X(i)).build());
+        }
+
+        // Number of nonzeros in the factor
+        let fill = |strategy: OrderingStrategy| {
+            let graph_order = graph.sparsity_pattern(strategy.order(&graph, &values));
+            let linear = graph.linearize(&values);
+            let DiffResult { value: r, diff: j } = linear.residual_jacobian(&graph_order);
+            let mut solver = CholeskySolver::default();
+            solver.set_order(&graph_order.order);
+            solver
+                .solve_lst_sq(j.as_ref(), r.as_ref())
+                .expect("Solve failed");
+            let cache = solver.sparsity_pattern.as_ref().expect("Missing analysis");
+            cache.symbolic.len_values()
+        };
+
+        // Inserted center first, so the factor is dense
+        let dim = 2 * (n as usize + 1);
+        assert_eq!(fill(OrderingStrategy::Natural), dim * (dim + 1) / 2);
+
+        // Anything putting the center last keeps the leaves independent
+        let sparse = fill(OrderingStrategy::Amd);
+        assert!(sparse < dim * (dim + 1) / 2);
+        assert_eq!(fill(OrderingStrategy::Colamd), sparse);
+        let constrained = OrderingStrategy::ConstrainedColamd(vec![X(0).into()]);
+        assert_eq!(fill(constrained), sparse);
+        let keys = (1..=n).chain([0]).map(|i| X(i).into());
+        let custom = OrderingStrategy::Custom(ValuesOrder::from_keys(&values, keys));
+        assert_eq!(fill(custom), sparse);
+    }
+
+    #[test]
+    fn test_fallback_solver() {
+        let mut solver = FallbackSolver::<CholeskySolver, DampedSolver>::default();
+        solve(&mut solver);
+
+        let (a, b) = singular_system();
+        let x = solver
+            .solve_symmetric(a.as_ref(), b.as_ref())
+            .expect("Damping failed");
+        // Slightly biased by the damping
+        assert_matrix_eq!(x, mat![[2.0], [0.0]], comp = abs, tol = 1e-4);
+    }
 }
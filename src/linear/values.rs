@@ -1,4 +1,4 @@
-use std::collections::hash_map::Iter as HashMapIter;
+use indexmap::map::Iter as IndexMapIter;
 
 use crate::{
     containers::{Idx, Key, Symbol, Values, ValuesOrder},
@@ -84,7 +84,7 @@ impl LinearValues {
 
 pub struct Iter<'a> {
     values: &'a LinearValues,
-    idx: HashMapIter<'a, Key, Idx>,
+    idx: IndexMapIter<'a, Key, Idx>,
 }
 
 impl<'a> Iterator for Iter<'a> {
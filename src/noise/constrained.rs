@@ -0,0 +1,89 @@
+use core::fmt;
+
+use super::{EqualityConstraint, NoiseModel};
+use crate::{
+    dtype,
+    linalg::{Const, MatrixX, Vector, VectorX},
+};
+
+/// A hard constraint "noise" model.
+///
+/// Marks a [factor](crate::containers::Factor) as an equality constraint, ie
+/// its residual $c(\Theta)$ should be exactly zero. Rather than a tiny sigma,
+/// it holds the Lagrange multipliers $\lambda$ and penalty $\mu$ of an
+/// augmented Lagrangian, and whitens to
+/// $$
+/// \sqrt{\mu} c(\Theta) + \lambda / \sqrt{\mu}
+/// $$
+/// which, up to a constant, turns the factor's error into $\lambda^\top c +
+/// \frac{\mu}{2} ||c||^2$. These are updated between solves by the
+/// [AugmentedLagrangian](crate::optimizers::AugmentedLagrangian) optimizer;
+/// other optimizers will treat the factor as a quadratic penalty.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstrainedNoise<const N: usize> {
+    multiplier: Vector<N>,
+    mu: dtype,
+}
+
+impl<const N: usize> ConstrainedNoise<N> {
+    /// Create a new constraint with zero multipliers and unit penalty.
+    pub fn new() -> Self {
+        Self {
+            multiplier: Vector::<N>::zeros(),
+            mu: 1.0,
+        }
+    }
+
+    /// Current Lagrange multipliers
+    pub fn multiplier(&self) -> &Vector<N> {
+        &self.multiplier
+    }
+}
+
+impl<const N: usize> Default for ConstrainedNoise<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[factrs::mark]
+impl<const N: usize> NoiseModel for ConstrainedNoise<N> {
+    type Dim = Const<N>;
+
+    fn whiten_vec(&self, v: VectorX) -> VectorX {
+        let sqrt_mu = self.mu.sqrt();
+        let mut out = v * sqrt_mu;
+        out += self.multiplier / sqrt_mu;
+        out
+    }
+
+    fn whiten_mat(&self, m: MatrixX) -> MatrixX {
+        m * self.mu.sqrt()
+    }
+
+    fn constraint(&mut self) -> Option<&mut dyn EqualityConstraint> {
+        Some(self)
+    }
+}
+
+impl<const N: usize> EqualityConstraint for ConstrainedNoise<N> {
+    fn reset(&mut self, mu: dtype) {
+        self.multiplier = Vector::<N>::zeros();
+        self.mu = mu;
+    }
+
+    fn update_multiplier(&mut self, c: &VectorX) {
+        self.multiplier += c * self.mu;
+    }
+
+    fn set_mu(&mut self, mu: dtype) {
+        self.mu = mu;
+    }
+}
+
+impl<const N: usize> fmt::Display for ConstrainedNoise<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
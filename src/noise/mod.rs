@@ -5,13 +5,19 @@
 
 use std::fmt::{Debug, Display};
 
-use crate::linalg::{DimName, MatrixX, VectorX};
+use crate::{
+    dtype,
+    linalg::{Dim, MatrixX, VectorX},
+};
 
 /// The trait for a noise model.
 #[cfg_attr(feature = "serde", typetag::serde(tag = "tag"))]
-pub trait NoiseModel: Debug + Display {
+pub trait NoiseModel: Debug + Display + Send + Sync {
     /// The dimension of the noise model
-    type Dim: DimName
+    ///
+    /// [Dyn](crate::linalg::Dyn) for noise models whose size is only known at runtime, these
+    /// must override [dim](NoiseModel::dim).
+    type Dim: Dim
     where
         Self: Sized;
 
@@ -19,7 +25,7 @@ pub trait NoiseModel: Debug + Display {
     where
         Self: Sized,
     {
-        Self::Dim::USIZE
+        Self::Dim::try_to_usize().expect("Dynamic noise models must implement dim")
     }
 
     /// Whiten a vector
@@ -27,6 +33,30 @@ pub trait NoiseModel: Debug + Display {
 
     /// Whiten a matrix
     fn whiten_mat(&self, m: MatrixX) -> MatrixX;
+
+    /// Access to the multipliers and penalty of an equality constraint
+    ///
+    /// Returns None by default, only constraints need to implement this.
+    fn constraint(&mut self) -> Option<&mut dyn EqualityConstraint> {
+        None
+    }
+}
+
+/// Noise model of an equality constraint
+///
+/// Holds the state of an augmented Lagrangian, which is updated by the
+/// [AugmentedLagrangian](crate::optimizers::AugmentedLagrangian) optimizer
+/// between inner solves.
+pub trait EqualityConstraint {
+    /// Set the penalty $\mu$ and zero the multipliers
+    fn reset(&mut self, mu: dtype);
+
+    /// Step the multipliers using the current (unwhitened) residual, $\lambda
+    /// \leftarrow \lambda + \mu c$
+    fn update_multiplier(&mut self, c: &VectorX);
+
+    /// Change the penalty $\mu$, keeping the multipliers
+    fn set_mu(&mut self, mu: dtype);
 }
 
 #[cfg(feature = "serde")]
@@ -36,4 +66,7 @@ mod gaussian;
 pub use gaussian::GaussianNoise;
 
 mod unit;
-pub use unit::UnitNoise;
+pub use unit::{UnitNoise, UnitNoiseX};
+
+mod constrained;
+pub use constrained::ConstrainedNoise;
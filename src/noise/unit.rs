@@ -1,7 +1,7 @@
 use core::fmt;
 
 use super::NoiseModel;
-use crate::linalg::{Const, MatrixX, VectorX};
+use crate::linalg::{Const, Dyn, MatrixX, VectorX};
 
 /// A unit noise model.
 ///
@@ -29,3 +29,45 @@ impl<const N: usize> fmt::Display for UnitNoise<N> {
         write!(f, "{:?}", self)
     }
 }
+
+/// A unit noise model whose dimension is only known at runtime.
+///
+/// Used for factors built from residuals that are already whitened, such as a
+/// [LinearPriorResidual](crate::residuals::LinearPriorResidual) from
+/// marginalization.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitNoiseX {
+    dim: usize,
+}
+
+impl UnitNoiseX {
+    pub fn new(dim: usize) -> Self {
+        Self { dim }
+    }
+}
+
+#[factrs::mark]
+impl NoiseModel for UnitNoiseX {
+    type Dim = Dyn;
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn whiten_vec(&self, v: VectorX) -> VectorX {
+        debug_assert_eq!(v.len(), self.dim, "Dimension mismatch in UnitNoiseX");
+        v
+    }
+
+    fn whiten_mat(&self, m: MatrixX) -> MatrixX {
+        debug_assert_eq!(m.nrows(), self.dim, "Dimension mismatch in UnitNoiseX");
+        m
+    }
+}
+
+impl fmt::Display for UnitNoiseX {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -0,0 +1,303 @@
+use super::{GraphOptimizer, LevenMarquardt, OptError, OptResult};
+use crate::{
+    containers::{Graph, Values},
+    dtype,
+};
+
+/// Parameters for [AugmentedLagrangian]
+#[derive(Debug, Clone)]
+pub struct AugLagParams {
+    /// Initial penalty $\mu$
+    pub mu_init: dtype,
+    /// Factor to increase $\mu$ by when the violation doesn't drop enough
+    pub mu_factor: dtype,
+    /// Maximum penalty $\mu$
+    pub mu_max: dtype,
+    /// Required ratio of new to old violation to keep $\mu$ unchanged
+    pub violation_decrease: dtype,
+    /// Constraints are satisfied once the violation falls below this
+    pub violation_tol: dtype,
+    /// Maximum number of outer iterations
+    pub max_iterations: usize,
+}
+
+impl Default for AugLagParams {
+    fn default() -> Self {
+        Self {
+            mu_init: 1.0,
+            mu_factor: 10.0,
+            mu_max: 1e10,
+            violation_decrease: 0.25,
+            violation_tol: dtype::EPSILON.sqrt(),
+            max_iterations: 50,
+        }
+    }
+}
+
+/// Augmented Lagrangian optimizer for equality constraints
+///
+/// Wraps any [GraphOptimizer], defaulting to [LevenMarquardt], to enforce
+/// factors whose residual must be exactly zero. These are marked by using a
+/// [ConstrainedNoise](crate::noise::ConstrainedNoise) as their noise model.
+/// Each outer iteration runs the inner optimizer on the augmented Lagrangian,
+/// then steps the multipliers by $\lambda \leftarrow \lambda + \mu c$. If the
+/// constraint violation didn't drop sufficiently, the penalty $\mu$ is
+/// increased as well. See "Numerical Optimization" by Nocedal and Wright,
+/// chapter 17 for more details.
+///
+/// After optimizing, the remaining constraint violation $\sqrt{\sum_i
+/// ||c_i||^2}$ can be retrieved using [violation](AugmentedLagrangian::violation).
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{FactorBuilder, Graph, Values},
+///    noise::ConstrainedNoise,
+///    optimizers::AugmentedLagrangian,
+///    residuals::{BetweenResidual, PriorResidual},
+///    traits::*,
+///    variables::VectorVar2,
+/// };
+/// # assign_symbols!(X: VectorVar2);
+/// let mut graph = Graph::new();
+/// let res = PriorResidual::new(VectorVar2::new(0.0, 0.0));
+/// graph.add_factor(FactorBuilder::new1(res, X(0)).build());
+/// let res = PriorResidual::new(VectorVar2::new(2.0, 0.0));
+/// graph.add_factor(FactorBuilder::new1(res, X(1)).build());
+///
+/// // X(1) must be exactly one unit from X(0)
+/// let res = BetweenResidual::new(VectorVar2::new(1.0, 0.0));
+/// let factor = FactorBuilder::new2(res, X(0), X(1))
+///     .noise(ConstrainedNoise::new())
+///     .build();
+/// graph.add_factor(factor);
+///
+/// let mut values = Values::new();
+/// values.insert(X(0), VectorVar2::identity());
+/// values.insert(X(1), VectorVar2::identity());
+///
+/// let mut opt: AugmentedLagrangian = AugmentedLagrangian::new(graph);
+/// let result = opt.optimize(values).expect("Optimization failed");
+/// assert!(opt.violation() < 1e-3);
+/// ```
+pub struct AugmentedLagrangian<O: GraphOptimizer = LevenMarquardt> {
+    optimizer: O,
+    /// Parameters for the multiplier and penalty updates
+    pub params: AugLagParams,
+    violation: dtype,
+}
+
+impl<O: GraphOptimizer> AugmentedLagrangian<O> {
+    pub fn new(graph: Graph) -> Self {
+        Self {
+            optimizer: O::new(graph),
+            params: AugLagParams::default(),
+            violation: 0.0,
+        }
+    }
+
+    /// The underlying optimizer, can be used to change its parameters
+    pub fn optimizer(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+
+    pub fn graph(&self) -> &Graph {
+        self.optimizer.graph()
+    }
+
+    /// Norm of the stacked constraint residuals after the last optimization
+    pub fn violation(&self) -> dtype {
+        self.violation
+    }
+
+    fn optimize_inner(&mut self, values: Values) -> OptResult<Values> {
+        // Later runs may start at the minimum, where no step can decrease the error
+        let start = values.clone();
+        match self.optimizer.optimize(values) {
+            Err(OptError::FailedToStep) => {
+                log::warn!(
+                    "Inner augmented Lagrangian optimizer failed to step, continuing anyway"
+                );
+                Ok(start)
+            }
+            Err(OptError::MaxIterations(values)) => {
+                log::warn!(
+                    "Inner augmented Lagrangian optimizer hit max iterations, continuing anyway"
+                );
+                Ok(values)
+            }
+            result => result,
+        }
+    }
+
+    // Norm of all constraint residuals, optionally stepping the multipliers
+    fn constraint_violation(&mut self, values: &Values, update: bool) -> dtype {
+        let mut violation = 0.0;
+        for factor in self.optimizer.graph_mut().iter_mut() {
+            let c = factor.residual(values);
+            if let Some(constraint) = factor.noise_mut().constraint() {
+                violation += c.norm_squared();
+                if update {
+                    constraint.update_multiplier(&c);
+                }
+            }
+        }
+        violation.sqrt()
+    }
+
+    fn set_mu(&mut self, mu: dtype) {
+        for factor in self.optimizer.graph_mut().iter_mut() {
+            if let Some(constraint) = factor.noise_mut().constraint() {
+                constraint.set_mu(mu);
+            }
+        }
+    }
+
+    /// Run the inner optimizer, updating the multipliers and penalty of all
+    /// constraints between each run
+    pub fn optimize(&mut self, mut values: Values) -> OptResult<Values> {
+        let mut mu = self.params.mu_init;
+        let mut num_constraints = 0;
+        for factor in self.optimizer.graph_mut().iter_mut() {
+            if let Some(constraint) = factor.noise_mut().constraint() {
+                constraint.reset(mu);
+                num_constraints += 1;
+            }
+        }
+        log::info!(
+            "Running augmented Lagrangian on {} constraints",
+            num_constraints
+        );
+
+        let mut violation_prev = self.constraint_violation(&values, false);
+        for i in 0..self.params.max_iterations {
+            values = self.optimize_inner(values)?;
+
+            self.violation = self.constraint_violation(&values, true);
+            log::info!("{:^5} | {:^12.4e} | {:^12.4e}", i, self.violation, mu);
+            if self.violation <= self.params.violation_tol {
+                log::info!("Augmented Lagrangian finished after {} iterations", i + 1);
+                return Ok(values);
+            }
+
+            if self.violation > self.params.violation_decrease * violation_prev {
+                mu = (mu * self.params.mu_factor).min(self.params.mu_max);
+                self.set_mu(mu);
+            }
+            violation_prev = self.violation;
+        }
+
+        Err(OptError::MaxIterations(values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        linalg::VectorX,
+        noise::{ConstrainedNoise, GaussianNoise},
+        optimizers::GaussNewton,
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::X,
+        variables::{Variable, VectorVar2, SE2},
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
+
+    fn check<O: GraphOptimizer>() {
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(VectorVar2::new(0.0, 0.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let res = PriorResidual::new(VectorVar2::new(2.0, 0.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(1)).build());
+        let res = BetweenResidual::new(VectorVar2::new(1.0, 0.0));
+        graph.add_factor(
+            FactorBuilder::new2_unchecked(res, X(0), X(1))
+                .noise(ConstrainedNoise::new())
+                .build(),
+        );
+
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::identity());
+
+        let mut opt = AugmentedLagrangian::<O>::new(graph);
+        opt.params.violation_tol = TOL;
+        let values = opt.optimize(values).expect("Optimization failed");
+        assert!(opt.violation() <= TOL);
+
+        // Prior errors are split evenly about the constraint
+        let x0: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x1: &VectorVar2 = values.get_unchecked(X(1)).expect("Missing X(1)");
+        assert_matrix_eq!(
+            x0.0,
+            VectorVar2::new(0.5, 0.0).0,
+            comp = abs,
+            tol = 10.0 * TOL
+        );
+        assert_matrix_eq!(
+            x1.0,
+            VectorVar2::new(1.5, 0.0).0,
+            comp = abs,
+            tol = 10.0 * TOL
+        );
+    }
+
+    #[test]
+    fn linear() {
+        check::<GaussNewton>();
+        check::<LevenMarquardt>();
+    }
+
+    #[test]
+    fn loop_closure() {
+        // Square of odometry that doesn't close, with an exact loop closure
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1_unchecked(PriorResidual::new(SE2::identity()), X(0)).build(),
+        );
+        let noise = GaussianNoise::<3>::from_scalar_sigma(0.1);
+        let delta = SE2::new(1.6, 1.0, 0.0);
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SE2::identity());
+        let mut pose = SE2::identity();
+        for i in 1..4 {
+            let res = BetweenResidual::new(delta.clone());
+            graph.add_factor(
+                FactorBuilder::new2_unchecked(res, X(i - 1), X(i))
+                    .noise(noise.clone())
+                    .build(),
+            );
+            pose = pose.compose(&delta);
+            values.insert_unchecked(X(i), pose.clone());
+        }
+        let closure = SE2::new(std::f64::consts::FRAC_PI_2 as dtype, 1.0, 0.0);
+        let res = BetweenResidual::new(closure.clone());
+        graph.add_factor(
+            FactorBuilder::new2_unchecked(res, X(3), X(0))
+                .noise(ConstrainedNoise::new())
+                .build(),
+        );
+
+        let mut opt: AugmentedLagrangian = AugmentedLagrangian::new(graph);
+        opt.params.violation_tol = TOL;
+        let values = opt.optimize(values).expect("Optimization failed");
+        assert!(opt.violation() <= TOL);
+
+        let x0: &SE2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x3: &SE2 = values.get_unchecked(X(3)).expect("Missing X(3)");
+        assert_matrix_eq!(
+            x3.compose(&closure).ominus(x0),
+            VectorX::zeros(3),
+            comp = abs,
+            tol = 10.0 * TOL
+        );
+    }
+}
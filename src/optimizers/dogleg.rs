@@ -0,0 +1,248 @@
+use std::{ops::Mul, time::Instant};
+
+use faer_ext::IntoNalgebra;
+
+use super::{
+    graph_order_cache::GraphOrderCache, macros::impl_with_solver, GraphOptimizer, IterationRecord,
+    IterationSummary, ObserverControl, OptError, OptObserverVec, OptParams, OptResult, Optimizer,
+};
+use crate::{
+    containers::{Graph, Values},
+    dtype,
+    linalg::{DiffResult, VectorX},
+    linear::{CholeskySolver, LinearSolver, LinearValues},
+};
+
+pub struct DoglegParams {
+    pub radius_init: dtype,
+    pub radius_min: dtype,
+    pub radius_max: dtype,
+}
+
+impl Default for DoglegParams {
+    fn default() -> Self {
+        Self {
+            radius_init: 1.0,
+            radius_min: 1e-10,
+            radius_max: 1e5,
+        }
+    }
+}
+
+/// Powell's Dogleg optimizer
+///
+/// A trust-region method that blends the Gauss-Newton step with the steepest
+/// descent (Cauchy) step. Each iteration solves $A \Delta \Theta = b$ once,
+/// and if a step is rejected, only the trust region radius is shrunk, so no
+/// additional factorizations are needed. Parameters can be modified using the
+/// `params_base` and `params_dogleg` fields, and observers add using
+/// `observers`. Additionally, is generic over the linear solver, but defaults
+/// to [CholeskySolver]. See the [linear](crate::linear) module for more linear
+/// solver options.
+pub struct Dogleg<S: LinearSolver = CholeskySolver> {
+    graph: Graph,
+    solver: S,
+    /// Basic parameters for the optimizer
+    pub params_base: OptParams,
+    /// Dogleg specific parameters
+    pub params_dogleg: DoglegParams,
+    /// Observers for the optimizer
+    pub observers: OptObserverVec<Values>,
+    radius: dtype,
+    // For caching computation between steps
+    graph_order: GraphOrderCache,
+    summary: IterationSummary,
+    start: Instant,
+}
+
+impl<S: LinearSolver> Dogleg<S> {
+    pub fn new(graph: Graph) -> Self {
+        let params_dogleg = DoglegParams::default();
+        Self {
+            graph,
+            solver: S::default(),
+            params_base: OptParams::default(),
+            radius: params_dogleg.radius_init,
+            params_dogleg,
+            observers: OptObserverVec::default(),
+            graph_order: GraphOrderCache::default(),
+            summary: IterationSummary::default(),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// Combine the Gauss-Newton and steepest descent steps for a given radius
+    fn dogleg_step(&self, h_gn: &VectorX, h_sd: &VectorX) -> VectorX {
+        let norm_gn = h_gn.norm();
+        let norm_sd = h_sd.norm();
+
+        if norm_gn <= self.radius {
+            // Gauss-Newton step is inside the trust region
+            h_gn.clone()
+        } else if norm_sd >= self.radius {
+            // Even steepest descent leaves it, truncate it to the boundary
+            h_sd * (self.radius / norm_sd)
+        } else {
+            // Walk from the Cauchy point towards Gauss-Newton until hitting the boundary
+            let diff = h_gn - h_sd;
+            let a = diff.norm_squared();
+            let b = 2.0 * h_sd.dot(&diff);
+            let c = norm_sd * norm_sd - self.radius * self.radius;
+            let beta = (-b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a);
+            h_sd + diff * beta
+        }
+    }
+}
+
+impl_with_solver!(Dogleg);
+
+impl<S: LinearSolver> Optimizer for Dogleg<S> {
+    type Input = Values;
+
+    fn params(&self) -> &OptParams {
+        &self.params_base
+    }
+
+    fn error(&self, values: &Values) -> dtype {
+        self.graph.error(values)
+    }
+
+    fn init(&mut self, values: &Values) {
+        self.radius = self.params_dogleg.radius_init;
+        let graph_order = self
+            .graph_order
+            .init(&self.graph, values, &self.params_base.ordering);
+        self.solver.set_order(&graph_order.order);
+        self.start = Instant::now();
+    }
+
+    fn step_summary(&self) -> IterationSummary {
+        self.summary.clone()
+    }
+
+    fn notify(&self, values: &Values, record: IterationRecord) -> ObserverControl {
+        self.observers.notify_graph(&self.graph, values, record)
+    }
+
+    fn step(&mut self, mut values: Values, _idx: usize) -> OptResult<Values> {
+        let order = &self.graph_order.get().order;
+
+        // Solve the linear system
+        self.summary = IterationSummary::default();
+        let start = Instant::now();
+        let linear_graph = self.graph.linearize(&values);
+        let DiffResult { value: r, diff: j } =
+            linear_graph.residual_jacobian(self.graph_order.get());
+
+        // Gauss-Newton step, only solved once per iteration
+        self.summary.time_linearize = start.elapsed();
+        let start = Instant::now();
+        let h_gn = self
+            .solver
+            .solve_lst_sq(j.as_ref(), r.as_ref())
+            .map_err(|e| OptError::invalid_system(e, order))?
+            .as_ref()
+            .into_nalgebra()
+            .column(0)
+            .clone_owned();
+
+        // Steepest descent step, scaled to the minimum along the gradient
+        let g = j.as_ref().transpose().mul(&r);
+        let jg = j.as_ref().mul(&g);
+        let g = g.as_ref().into_nalgebra().column(0).clone_owned();
+        let jg = jg.as_ref().into_nalgebra().column(0).clone_owned();
+        let jg_norm2 = jg.norm_squared();
+        let alpha = if jg_norm2 > 0.0 {
+            g.norm_squared() / jg_norm2
+        } else {
+            0.0
+        };
+        let h_sd = g * alpha;
+        self.summary.time_solve = start.elapsed();
+
+        let error_old = self.graph.error(&values);
+        let linear_error_old = linear_graph.error(&LinearValues::zero_from_order(order.clone()));
+
+        // If even the full Gauss-Newton step can't make progress, we're converged
+        let dx_gn = LinearValues::from_order_and_vector(order.clone(), h_gn.clone());
+        if linear_error_old - linear_graph.error(&dx_gn) <= self.params_base.error_tol_absolute {
+            return Ok(values);
+        }
+
+        let start = Instant::now();
+        loop {
+            let h = self.dogleg_step(&h_gn, &h_sd);
+            let h_norm = h.norm();
+            let dx = LinearValues::from_order_and_vector(order.clone(), h);
+
+            // Compare the actual decrease to what the linear model predicted
+            let mut values_new = values.clone();
+            values_new.oplus_mut(&dx);
+            let decrease_actual = error_old - self.graph.error(&values_new);
+            let decrease_pred = linear_error_old - linear_graph.error(&dx);
+
+            if decrease_actual > 0.0 && decrease_pred > 0.0 {
+                // Update the trust region based on how well the model fit
+                let rho = decrease_actual / decrease_pred;
+                if rho > 0.75 {
+                    self.radius = (3.0 * h_norm)
+                        .max(self.radius)
+                        .min(self.params_dogleg.radius_max);
+                } else if rho < 0.25 {
+                    self.radius *= 0.5;
+                }
+                values = values_new;
+                self.summary.step_norm = h_norm;
+                break;
+            }
+
+            // Step was rejected, shrink the trust region and try again
+            self.radius = 0.5 * h_norm.min(self.radius);
+            if self.radius < self.params_dogleg.radius_min {
+                return Err(OptError::FailedToStep);
+            }
+            values = self.params_base.interrupted(self.start, values)?;
+        }
+
+        self.summary.time_update = start.elapsed();
+        Ok(values)
+    }
+}
+
+impl<S: LinearSolver> GraphOptimizer for Dogleg<S> {
+    fn new(graph: Graph) -> Self {
+        Self::new(graph)
+    }
+
+    fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    fn graph_mut(&mut self) -> &mut Graph {
+        self.graph_order.clear();
+        &mut self.graph
+    }
+
+    fn add_factors(&mut self, factors: Graph) {
+        for factor in factors {
+            self.graph.add_factor(factor);
+        }
+        self.graph_order.add_factors();
+    }
+
+    fn add_values(&mut self, values: &Values) {
+        self.graph_order.add_values(values);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_optimizer;
+
+    test_optimizer!(Dogleg);
+}
@@ -0,0 +1,470 @@
+use foldhash::{HashMap, HashSet};
+use indexmap::map::Entry;
+
+use super::{GraphOptimizer, LevenMarquardt, OptError};
+use crate::{
+    containers::{Factor, Graph, Key, Values},
+    dtype,
+    linalg::{MatrixX, VectorX},
+    residuals::LinearPriorResidual,
+};
+
+/// Fixed-lag smoother
+///
+/// Optimizes over a sliding window of variables using any [GraphOptimizer],
+/// defaulting to [LevenMarquardt]. Each variable is given a timestamp when it's
+/// added, and once it's older than `lag` compared to the latest timestamp it's
+/// marginalized out. Rather than simply dropping the factors it touched, they
+/// are linearized and the old variables removed via the Schur complement,
+/// leaving a dense [LinearPriorResidual] on the remaining variables. This keeps
+/// the size of the problem bounded while retaining the information from the
+/// past.
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{FactorBuilder, Graph, Values},
+///    dtype,
+///    optimizers::{FixedLagSmoother, GaussNewton},
+///    residuals::{BetweenResidual, PriorResidual},
+///    traits::*,
+///    variables::SE2,
+/// };
+/// # assign_symbols!(X: SE2);
+/// let mut smoother = FixedLagSmoother::<GaussNewton>::new(1.0);
+///
+/// let mut graph = Graph::new();
+/// graph.add_factor(FactorBuilder::new1(PriorResidual::new(SE2::identity()), X(0)).build());
+/// let mut values = Values::new();
+/// values.insert(X(0), SE2::identity());
+/// smoother.update(graph, values, [(X(0).into(), 0.0)]).expect("Update failed");
+///
+/// for i in 1..5 {
+///     let mut graph = Graph::new();
+///     let delta = SE2::new(0.1, 1.0, 0.0);
+///     graph.add_factor(FactorBuilder::new2(BetweenResidual::new(delta), X(i - 1), X(i)).build());
+///     let mut values = Values::new();
+///     values.insert(X(i), SE2::identity());
+///     let timestamps = [(X(i).into(), i as dtype)];
+///     smoother.update(graph, values, timestamps).expect("Update failed");
+/// }
+///
+/// // Only variables within the lag remain
+/// assert_eq!(smoother.values().len(), 2);
+/// ```
+pub struct FixedLagSmoother<O: GraphOptimizer = LevenMarquardt> {
+    optimizer: O,
+    values: Values,
+    timestamps: HashMap<Key, dtype>,
+    /// Length of the window, variables older than this are marginalized
+    pub lag: dtype,
+}
+
+impl<O: GraphOptimizer> FixedLagSmoother<O> {
+    pub fn new(lag: dtype) -> Self {
+        Self {
+            optimizer: O::new(Graph::new()),
+            values: Values::new(),
+            timestamps: HashMap::default(),
+            lag,
+        }
+    }
+
+    /// The underlying optimizer, can be used to change its parameters
+    pub fn optimizer(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+
+    /// Factors in the current window
+    pub fn graph(&self) -> &Graph {
+        self.optimizer.graph()
+    }
+
+    /// Current estimate of the variables in the window
+    pub fn values(&self) -> &Values {
+        &self.values
+    }
+
+    /// Time each variable in the window was added at
+    pub fn timestamps(&self) -> &HashMap<Key, dtype> {
+        &self.timestamps
+    }
+
+    /// Add new factors and variables, optimize, then marginalize anything
+    /// older than the lag.
+    ///
+    /// `timestamps` gives the time of each new variable, and can also move the
+    /// timestamp of a variable already in the window. New variables without one
+    /// are stamped with the latest time seen so far. Values for keys that are
+    /// already present are ignored. If the optimizer hits its maximum number
+    /// of iterations or is interrupted, the result is kept anyway.
+    ///
+    /// On any other error the new factors, variables and timestamps are kept,
+    /// but the estimate is left as it was and nothing is marginalized. Both
+    /// happen on the next successful update.
+    pub fn update(
+        &mut self,
+        new_factors: Graph,
+        new_values: Values,
+        timestamps: impl IntoIterator<Item = (Key, dtype)>,
+    ) -> Result<(), OptError<Values>> {
+        let fixed = new_values.fixed().copied().collect::<Vec<_>>();
+        let mut new_keys = Vec::new();
+        for (key, value) in new_values {
+            if let Entry::Vacant(e) = self.values.entry(key) {
+                e.insert(value);
+                new_keys.push(key);
+            }
+        }
+
+        for (key, t) in timestamps {
+            if self.values.get_raw(key).is_some() {
+                self.timestamps.insert(key, t);
+            } else {
+                log::warn!("Ignoring timestamp for {:?}, which has no value", key);
+            }
+        }
+        let time = self
+            .timestamps
+            .values()
+            .copied()
+            .reduce(dtype::max)
+            .unwrap_or(0.0);
+        for key in new_keys {
+            self.timestamps.entry(key).or_insert(time);
+        }
+        for key in fixed {
+            self.values.fix(key);
+        }
+        let graph = self.optimizer.graph_mut();
+        for factor in new_factors {
+            graph.add_factor(factor);
+        }
+
+        self.values = match self.optimizer.optimize(self.values.clone()) {
+            Ok(values) => values,
+            Err(OptError::MaxIterations(values)) => {
+                log::warn!("Fixed-lag smoother hit max iterations, using the result anyway");
+                values
+            }
+            Err(OptError::Cancelled(values) | OptError::TimeLimit(values)) => {
+                log::warn!("Fixed-lag smoother was interrupted, using the result anyway");
+                values
+            }
+            Err(e) => return Err(e),
+        };
+
+        let marginalize = self
+            .timestamps
+            .iter()
+            .filter(|(_, t)| **t < time - self.lag)
+            .map(|(k, _)| *k)
+            .collect::<HashSet<_>>();
+        if !marginalize.is_empty() {
+            self.marginalize(&marginalize);
+        }
+
+        Ok(())
+    }
+
+    /// Marginalize out a set of variables, replacing all the factors they
+    /// touch with a single linear prior on the remaining variables.
+    fn marginalize(&mut self, keys: &HashSet<Key>) {
+        let removed = self
+            .optimizer
+            .graph_mut()
+            .remove_factors(|f| f.keys().iter().any(|k| keys.contains(k)));
+
+        // Marginalized variables first, then the separator
+        let mut separator = removed
+            .iter()
+            .flat_map(|f| f.keys().iter().copied())
+            .filter(|k| !keys.contains(k))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        separator.sort_by_key(|k| k.0);
+        let mut marginal = keys.iter().copied().collect::<Vec<_>>();
+        marginal.sort_by_key(|k| k.0);
+
+        let mut offsets = HashMap::default();
+        let mut dim = 0;
+        for key in marginal.iter().chain(separator.iter()) {
+            offsets.insert(*key, dim);
+            dim += self
+                .values
+                .get_raw(*key)
+                .expect("Key missing in values")
+                .dim();
+        }
+        let dim_m = separator.first().map_or(dim, |k| offsets[k]);
+        let dim_s = dim - dim_m;
+
+        // Build the information matrix and vector of the removed factors
+        let mut h = MatrixX::zeros(dim, dim);
+        let mut g = VectorX::zeros(dim);
+        for factor in removed.iter() {
+            let linear = factor.linearize(&self.values);
+            for (i, ki) in linear.keys.iter().enumerate() {
+                let ai = linear.a.get_block(i);
+                let oi = offsets[ki];
+                let mut gi = g.rows_mut(oi, ai.ncols());
+                gi += ai.transpose() * &linear.b;
+                for (j, kj) in linear.keys.iter().enumerate() {
+                    let aj = linear.a.get_block(j);
+                    let mut hij = h.view_mut((oi, offsets[kj]), (ai.ncols(), aj.ncols()));
+                    hij += ai.transpose() * aj;
+                }
+            }
+        }
+
+        for key in marginal.iter() {
+            self.timestamps.remove(key);
+            self.values.remove_raw(*key);
+        }
+        let mut values = Values::new();
+        for key in separator.iter() {
+            let value = self.values.get_raw(*key).expect("Key missing in values");
+            values.entry(*key).or_insert(value.clone_box());
+        }
+
+        if separator.is_empty() {
+            return;
+        }
+
+        // Schur complement onto the separator
+        let h_mm = h.view((0, 0), (dim_m, dim_m));
+        let h_sm = h.view((dim_m, 0), (dim_s, dim_m));
+        let h_mm_inv = h_mm
+            .into_owned()
+            .pseudo_inverse(dtype::EPSILON)
+            .expect("Failed to invert marginalized block");
+        let h_schur = h.view((dim_m, dim_m), (dim_s, dim_s)) - h_sm * &h_mm_inv * h_sm.transpose();
+        let g_schur = g.rows(dim_m, dim_s) - h_sm * &h_mm_inv * g.rows(0, dim_m);
+
+        // Factor into R^T R, only keeping the observable directions
+        let eigen = h_schur.symmetric_eigen();
+        let tol = dtype::EPSILON * eigen.eigenvalues.amax() * (dim_s as dtype);
+        let rows = eigen
+            .eigenvalues
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| **l > tol)
+            .map(|(i, l)| (eigen.eigenvectors.column(i).transpose(), *l))
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            return;
+        }
+
+        let r = MatrixX::from_fn(rows.len(), dim_s, |i, j| rows[i].0[j] * rows[i].1.sqrt());
+        let d = VectorX::from_fn(rows.len(), |i, _| {
+            rows[i].0.dot(&g_schur.transpose()) / rows[i].1.sqrt()
+        });
+
+        let residual = LinearPriorResidual::new(values, r, d);
+        let factor = Factor::new_dynamic(separator, Box::new(residual));
+        self.optimizer.graph_mut().add_factor(factor);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        noise::GaussianNoise,
+        optimizers::{GaussNewton, Optimizer},
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::X,
+        variables::{Variable, VectorVar2, SE2},
+    };
+
+    fn prior() -> Factor {
+        let noise = GaussianNoise::<2>::from_diag_sigmas(0.1, 0.2);
+        FactorBuilder::new1_unchecked(PriorResidual::new(VectorVar2::new(1.0, 2.0)), X(0))
+            .noise(noise)
+            .build()
+    }
+
+    fn between(i: u32) -> Factor {
+        let noise = GaussianNoise::<2>::from_diag_sigmas(0.3, 0.4);
+        let delta = VectorVar2::new(1.0, 0.5 * i as dtype);
+        FactorBuilder::new2_unchecked(BetweenResidual::new(delta), X(i - 1), X(i))
+            .noise(noise)
+            .build()
+    }
+
+    #[test]
+    fn matches_batch() {
+        // On a linear problem, marginalization should be exact
+        let mut smoother = FixedLagSmoother::<GaussNewton>::new(2.5);
+        let mut batch = Graph::new();
+        let mut initial = Values::new();
+
+        let mut graph = Graph::new();
+        graph.add_factor(prior());
+        batch.add_factor(prior());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        initial.insert_unchecked(X(0), VectorVar2::identity());
+        smoother
+            .update(graph, values, [(X(0).into(), 0.0)])
+            .expect("Update failed");
+
+        for i in 1..10 {
+            let mut graph = Graph::new();
+            graph.add_factor(between(i));
+            batch.add_factor(between(i));
+            let mut values = Values::new();
+            values.insert_unchecked(X(i), VectorVar2::identity());
+            initial.insert_unchecked(X(i), VectorVar2::identity());
+            smoother
+                .update(graph, values, [(X(i).into(), i as dtype)])
+                .expect("Update failed");
+        }
+
+        // Window is bounded
+        assert_eq!(smoother.values().len(), 3);
+        assert_eq!(smoother.graph().len(), 3);
+
+        let mut opt: GaussNewton = GaussNewton::new(batch);
+        let expected = opt.optimize(initial).expect("Optimization failed");
+        for i in 7..10 {
+            let got: &VectorVar2 = smoother.values().get_unchecked(X(i)).expect("Missing X");
+            let exp: &VectorVar2 = expected.get_unchecked(X(i)).expect("Missing X");
+            assert_matrix_eq!(got.0, exp.0, comp = abs, tol = 1e-6);
+        }
+    }
+
+    #[test]
+    fn nonlinear() {
+        let mut smoother = FixedLagSmoother::<GaussNewton>::new(1.5);
+
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1_unchecked(PriorResidual::new(SE2::identity()), X(0)).build(),
+        );
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SE2::identity());
+        smoother
+            .update(graph, values, [(X(0).into(), 0.0)])
+            .expect("Update failed");
+
+        let delta = SE2::new(0.2, 1.0, 0.0);
+        let mut truth = SE2::identity();
+        for i in 1..8 {
+            truth = truth.compose(&delta);
+            let mut graph = Graph::new();
+            graph.add_factor(
+                FactorBuilder::new2_unchecked(BetweenResidual::new(delta.clone()), X(i - 1), X(i))
+                    .build(),
+            );
+            let mut values = Values::new();
+            values.insert_unchecked(X(i), truth.clone());
+            smoother
+                .update(graph, values, [(X(i).into(), i as dtype)])
+                .expect("Update failed");
+        }
+
+        let got: &SE2 = smoother.values().get_unchecked(X(7)).expect("Missing X");
+        assert_matrix_eq!(
+            got.ominus(&truth),
+            VectorX::zeros(3),
+            comp = abs,
+            tol = 1e-6
+        );
+        assert!(smoother.values().get_unchecked::<_, SE2>(X(5)).is_none());
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut smoother = FixedLagSmoother::<GaussNewton>::new(1.0);
+
+        let mut graph = Graph::new();
+        graph.add_factor(prior());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        smoother
+            .update(graph, values, [(X(0).into(), 0.0)])
+            .expect("Update failed");
+
+        // Variables added together can have different times
+        let mut graph = Graph::new();
+        graph.add_factor(between(1));
+        graph.add_factor(between(2));
+        let mut values = Values::new();
+        values.insert_unchecked(X(1), VectorVar2::identity());
+        values.insert_unchecked(X(2), VectorVar2::identity());
+        smoother
+            .update(graph, values, [(X(1).into(), 1.5), (X(2).into(), 2.0)])
+            .expect("Update failed");
+        assert!(smoother
+            .values()
+            .get_unchecked::<_, VectorVar2>(X(0))
+            .is_none());
+        assert_eq!(smoother.values().len(), 2);
+
+        // Without a timestamp, the latest one is used
+        let mut graph = Graph::new();
+        graph.add_factor(between(3));
+        let mut values = Values::new();
+        values.insert_unchecked(X(3), VectorVar2::identity());
+        smoother.update(graph, values, []).expect("Update failed");
+        assert_eq!(smoother.timestamps()[&X(3).into()], 2.0);
+        assert_eq!(smoother.values().len(), 3);
+    }
+
+    #[test]
+    fn failed_update() {
+        let mut smoother = FixedLagSmoother::<GaussNewton>::new(0.5);
+
+        let mut graph = Graph::new();
+        graph.add_factor(prior());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        smoother
+            .update(graph, values, [(X(0).into(), 0.0)])
+            .expect("Update failed");
+        let x0 = smoother
+            .values()
+            .get_unchecked::<_, VectorVar2>(X(0))
+            .expect("Missing X")
+            .0;
+
+        // Nothing anchors X(1) and X(2), so the system can't be solved
+        let mut graph = Graph::new();
+        graph.add_factor(between(2));
+        let mut values = Values::new();
+        values.insert_unchecked(X(1), VectorVar2::identity());
+        values.insert_unchecked(X(2), VectorVar2::identity());
+        let timestamps = [(X(1).into(), 1.0), (X(2).into(), 1.0)];
+        assert!(matches!(
+            smoother.update(graph, values, timestamps),
+            Err(OptError::InvalidSystem { .. })
+        ));
+
+        // The new factors and variables are kept, but the estimate isn't changed
+        // and X(0) isn't marginalized yet
+        assert_eq!(smoother.graph().len(), 2);
+        assert_eq!(smoother.values().len(), 3);
+        let got = smoother
+            .values()
+            .get_unchecked::<_, VectorVar2>(X(0))
+            .expect("Missing X");
+        assert_eq!(got.0, x0);
+
+        // Connecting them fixes it, and catches up on marginalization
+        let mut graph = Graph::new();
+        graph.add_factor(between(1));
+        smoother
+            .update(graph, Values::new(), [])
+            .expect("Update failed");
+        assert!(smoother
+            .values()
+            .get_unchecked::<_, VectorVar2>(X(0))
+            .is_none());
+        assert_eq!(smoother.values().len(), 2);
+    }
+}
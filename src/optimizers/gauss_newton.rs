@@ -1,12 +1,55 @@
+use std::{ops::Mul, time::Instant};
+
 use faer_ext::IntoNalgebra;
 
-use super::{OptObserverVec, OptParams, OptResult, Optimizer};
+use super::{
+    graph_order_cache::GraphOrderCache, macros::impl_with_solver, GraphOptimizer, IterationRecord,
+    IterationSummary, ObserverControl, OptError, OptObserverVec, OptParams, OptResult, Optimizer,
+};
 use crate::{
-    containers::{Graph, GraphOrder, Values, ValuesOrder},
-    linalg::DiffResult,
+    containers::{Graph, Values, ValuesOrder},
+    dtype,
+    linalg::{DiffResult, VectorX},
     linear::{CholeskySolver, LinearSolver, LinearValues},
 };
 
+/// Line search methods for [GaussNewton]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineSearch {
+    /// Always take the full step
+    #[default]
+    None,
+    /// Backtrack by a constant factor until the Armijo condition is met
+    Armijo,
+    /// Backtrack using quadratic, then cubic interpolation of the error until
+    /// the Armijo condition is met
+    Cubic,
+}
+
+/// Gauss-Newton specific parameters
+#[derive(Debug, Clone)]
+pub struct GaussNewtonParams {
+    /// Line search to use along the Gauss-Newton direction
+    pub line_search: LineSearch,
+    /// Fraction of the predicted decrease required by the Armijo condition
+    pub armijo_c: dtype,
+    /// Factor to shrink the step length by when backtracking
+    pub backtrack_factor: dtype,
+    /// Smallest step length to try before giving up
+    pub step_min: dtype,
+}
+
+impl Default for GaussNewtonParams {
+    fn default() -> Self {
+        Self {
+            line_search: LineSearch::default(),
+            armijo_c: 1e-4,
+            backtrack_factor: 0.5,
+            step_min: 1e-10,
+        }
+    }
+}
+
 /// The Gauss-Newton optimizer
 ///
 /// Solves $A \Delta \Theta = b$ directly for each optimizer steps. Parameters
@@ -14,16 +57,23 @@ use crate::{
 /// `observers`. Additionally, is generic over the linear solver, but defaults
 /// to [CholeskySolver]. See the [linear](crate::linear) module for more linear
 /// solver options.
+///
+/// On poorly initialized problems the full step may overshoot, in which case a
+/// [LineSearch] can be enabled via `params_gn` to scale it back along the
+/// retraction.
 #[derive(Default)]
 pub struct GaussNewton<S: LinearSolver = CholeskySolver> {
     graph: Graph,
     solver: S,
     /// Basic parameters for the optimizer
     pub params: OptParams,
+    /// Gauss-Newton specific parameters
+    pub params_gn: GaussNewtonParams,
     /// Observers for the optimizer
     pub observers: OptObserverVec<Values>,
     // For caching computation between steps
-    graph_order: Option<GraphOrder>,
+    graph_order: GraphOrderCache,
+    summary: IterationSummary,
 }
 
 impl<S: LinearSolver> GaussNewton<S> {
@@ -33,13 +83,94 @@ impl<S: LinearSolver> GaussNewton<S> {
             solver: S::default(),
             observers: OptObserverVec::default(),
             params: OptParams::default(),
-            graph_order: None,
+            params_gn: GaussNewtonParams::default(),
+            graph_order: GraphOrderCache::default(),
+            summary: IterationSummary::default(),
         }
     }
 
     pub fn graph(&self) -> &Graph {
         &self.graph
     }
+
+    /// Find a step length along `delta` satisfying the Armijo condition
+    ///
+    /// `slope` is the directional derivative of the error along `delta`.
+    fn line_search(
+        &self,
+        values: &Values,
+        order: &ValuesOrder,
+        delta: &VectorX,
+        slope: dtype,
+    ) -> Result<dtype, OptError<Values>> {
+        // Not enough predicted decrease to bother searching
+        if -slope <= self.params.error_tol_absolute {
+            return Ok(1.0);
+        }
+
+        let error = |alpha: dtype| {
+            let mut values = values.clone();
+            let dx = LinearValues::from_order_and_vector(order.clone(), delta * alpha);
+            values.oplus_mut(&dx);
+            self.graph.error(&values)
+        };
+
+        let error_0 = self.graph.error(values);
+        let mut alpha = 1.0;
+        let mut error_alpha = error(alpha);
+        let mut prev = None;
+        while error_alpha > error_0 + self.params_gn.armijo_c * alpha * slope {
+            let next = match self.params_gn.line_search {
+                LineSearch::Cubic => interpolate(error_0, slope, (alpha, error_alpha), prev),
+                _ => alpha * self.params_gn.backtrack_factor,
+            };
+            prev = Some((alpha, error_alpha));
+            alpha = next;
+
+            if alpha < self.params_gn.step_min {
+                return Err(OptError::LineSearchFailed);
+            }
+            error_alpha = error(alpha);
+        }
+
+        Ok(alpha)
+    }
+}
+
+impl_with_solver!(GaussNewton);
+
+/// Minimizer of a quadratic or cubic fit of the error along the line
+///
+/// Uses the error and slope at 0 along with the current and previous trials,
+/// see Nocedal & Wright section 3.5. Safeguarded to stay within [0.1, 0.5] of
+/// the current step length.
+pub(super) fn interpolate(
+    error_0: dtype,
+    slope: dtype,
+    (a1, e1): (dtype, dtype),
+    prev: Option<(dtype, dtype)>,
+) -> dtype {
+    let next = match prev {
+        None => -slope * a1 * a1 / (2.0 * (e1 - error_0 - slope * a1)),
+        Some((a0, e0)) => {
+            let r1 = e1 - error_0 - slope * a1;
+            let r0 = e0 - error_0 - slope * a0;
+            let d = a0 * a0 * a1 * a1 * (a1 - a0);
+            let a = (a0 * a0 * r1 - a1 * a1 * r0) / d;
+            let b = (-a0 * a0 * a0 * r1 + a1 * a1 * a1 * r0) / d;
+            if a == 0.0 {
+                -slope / (2.0 * b)
+            } else {
+                (-b + (b * b - 3.0 * a * slope).sqrt()) / (3.0 * a)
+            }
+        }
+    };
+
+    if next.is_finite() {
+        next.clamp(0.1 * a1, 0.5 * a1)
+    } else {
+        0.5 * a1
+    }
 }
 
 impl<S: LinearSolver> Optimizer for GaussNewton<S> {
@@ -53,51 +184,337 @@ impl<S: LinearSolver> Optimizer for GaussNewton<S> {
         &self.params
     }
 
-    fn init(&mut self, _values: &Values) {
-        // TODO: Some way to manual specify how to computer ValuesOrder
-        // Precompute the sparsity pattern
-        self.graph_order = Some(
-            self.graph
-                .sparsity_pattern(ValuesOrder::from_values(_values)),
-        );
+    fn init(&mut self, values: &Values) {
+        let graph_order = self
+            .graph_order
+            .init(&self.graph, values, &self.params.ordering);
+        self.solver.set_order(&graph_order.order);
+    }
+
+    fn step_summary(&self) -> IterationSummary {
+        self.summary.clone()
     }
 
-    fn step(&mut self, mut values: Values, idx: usize) -> OptResult<Values> {
+    fn notify(&self, values: &Values, record: IterationRecord) -> ObserverControl {
+        self.observers.notify_graph(&self.graph, values, record)
+    }
+
+    fn step(&mut self, mut values: Values, _idx: usize) -> OptResult<Values> {
         // Solve the linear system
+        let start = Instant::now();
         let linear_graph = self.graph.linearize(&values);
         let DiffResult { value: r, diff: j } =
-            linear_graph.residual_jacobian(self.graph_order.as_ref().expect("Missing graph order"));
+            linear_graph.residual_jacobian(self.graph_order.get());
 
         // Solve Ax = b
+        self.summary.time_linearize = start.elapsed();
+        let start = Instant::now();
+        let order = &self.graph_order.get().order;
         let delta = self
             .solver
             .solve_lst_sq(j.as_ref(), r.as_ref())
+            .map_err(|e| OptError::invalid_system(e, order))?
             .as_ref()
             .into_nalgebra()
             .column(0)
             .clone_owned();
 
+        self.summary.time_solve = start.elapsed();
+
         // Update the values
-        let dx = LinearValues::from_order_and_vector(
-            self.graph_order
-                .as_ref()
-                .expect("Missing graph order")
-                .order
-                .clone(),
-            delta,
-        );
+        let start = Instant::now();
+        let alpha = match self.params_gn.line_search {
+            LineSearch::None => 1.0,
+            _ => {
+                let g = j.as_ref().transpose().mul(&r);
+                let g = g.as_ref().into_nalgebra().column(0).clone_owned();
+                self.line_search(&values, order, &delta, -g.dot(&delta))?
+            }
+        };
+        let delta = delta * alpha;
+        self.summary.step_norm = delta.norm();
+        let dx = LinearValues::from_order_and_vector(order.clone(), delta);
         values.oplus_mut(&dx);
-
-        self.observers.notify(&values, idx);
+        self.summary.time_update = start.elapsed();
 
         Ok(values)
     }
 }
 
+impl<S: LinearSolver> GraphOptimizer for GaussNewton<S> {
+    fn new(graph: Graph) -> Self {
+        Self::new(graph)
+    }
+
+    fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    fn graph_mut(&mut self) -> &mut Graph {
+        self.graph_order.clear();
+        &mut self.graph
+    }
+
+    fn add_factors(&mut self, factors: Graph) {
+        for factor in factors {
+            self.graph.add_factor(factor);
+        }
+        self.graph_order.add_factors();
+    }
+
+    fn add_values(&mut self, values: &Values) {
+        self.graph_order.add_values(values);
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::{cell::RefCell, rc::Rc};
+
     use super::*;
-    use crate::test_optimizer;
+    use crate::{
+        containers::FactorBuilder,
+        linalg::Vector3,
+        linear::{DampedSolver, FallbackSolver},
+        optimizers::{OptError, OptObserver, TerminationReason},
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::{L, X},
+        test_optimizer,
+        variables::{Variable, VectorVar2, SE2, SO3},
+    };
 
     test_optimizer!(GaussNewton);
+
+    fn problem() -> (GaussNewton, Values) {
+        let prior = SO3::exp(Vector3::new(0.5, -1.0, 0.3).as_view());
+        let mut graph = Graph::new();
+        graph.add_factor(FactorBuilder::new1_unchecked(PriorResidual::new(prior), X(0)).build());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SO3::identity());
+        (GaussNewton::new(graph), values)
+    }
+
+    #[test]
+    fn summary() {
+        let (mut opt, values) = problem();
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(result.is_ok());
+
+        assert!(summary.error_final < summary.error_initial);
+        assert!(!summary.iterations.is_empty());
+        assert!(summary.iterations[0].step_norm > 0.0);
+        assert_eq!(
+            summary.iterations.last().expect("Missing iteration").error,
+            summary.error_final
+        );
+        assert!(summary.lambda_history().is_empty());
+        assert!(matches!(
+            summary.termination,
+            TerminationReason::AbsoluteTol | TerminationReason::RelativeTol
+        ));
+    }
+
+    // Records every iteration and stops after the first
+    struct StopEarly(Rc<RefCell<Vec<IterationRecord>>>);
+
+    impl OptObserver for StopEarly {
+        type Input = Values;
+
+        fn on_step(&self, _values: &Values, record: &IterationRecord) -> ObserverControl {
+            self.0.borrow_mut().push(record.clone());
+            ObserverControl::Stop
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let (mut opt, values) = problem();
+        let records = Rc::new(RefCell::new(Vec::new()));
+        opt.observers.add(StopEarly(records.clone()));
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(result.is_ok());
+        assert_eq!(summary.termination, TerminationReason::ObserverStop);
+        assert_eq!(summary.iterations.len(), 1);
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].iteration, 1);
+        assert_eq!(records[0].error_before, summary.error_initial);
+        assert_eq!(records[0].error_after, summary.error_final);
+        assert_eq!(records[0].step_norm, summary.iterations[0].step_norm);
+        assert_eq!(records[0].factor_errors, vec![summary.error_final]);
+    }
+
+    #[test]
+    fn summary_max_iterations() {
+        let (mut opt, values) = problem();
+        opt.params.max_iterations = 1;
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(matches!(result, Err(OptError::MaxIterations(_))));
+        assert_eq!(summary.termination, TerminationReason::MaxIterations);
+        assert_eq!(summary.iterations.len(), 1);
+    }
+
+    #[test]
+    fn fixed() {
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(VectorVar2::new(1.0, 1.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let res = BetweenResidual::new(VectorVar2::new(1.0, 2.0));
+        graph.add_factor(FactorBuilder::new2_unchecked(res, X(0), X(1)).build());
+
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::new(5.0, 5.0));
+        values.insert_unchecked(X(1), VectorVar2::identity());
+        values.fix(X(0));
+
+        let mut opt: GaussNewton = GaussNewton::new(graph);
+        let values = opt.optimize(values).expect("Optimization failed");
+
+        // Fixed variable is untouched, but still used for the between
+        let x0: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x1: &VectorVar2 = values.get_unchecked(X(1)).expect("Missing X(1)");
+        assert_eq!(x0.0, VectorVar2::new(5.0, 5.0).0);
+        assert!((x1.0 - VectorVar2::new(6.0, 7.0).0).norm() < 1e-4);
+        assert!(values.is_fixed(X(0)));
+    }
+
+    #[test]
+    fn invalid_system() {
+        // Nothing constrains X(1)
+        let graph = || {
+            let mut graph = Graph::new();
+            let res = PriorResidual::new(VectorVar2::new(1.0, 1.0));
+            graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            graph
+        };
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::new(3.0, 4.0));
+
+        let mut opt: GaussNewton = GaussNewton::new(graph());
+        let (result, summary) = opt.optimize_with_summary(values.clone());
+        assert!(matches!(
+            result,
+            Err(OptError::InvalidSystem { key: Some(k), .. }) if k == X(1).into()
+        ));
+        assert_eq!(summary.termination, TerminationReason::InvalidSystem);
+
+        // Damping leaves X(1) where it is
+        let mut opt: GaussNewton<FallbackSolver<CholeskySolver, DampedSolver>> =
+            GaussNewton::new(graph());
+        let values = opt.optimize(values).expect("Optimization failed");
+        let x0: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x1: &VectorVar2 = values.get_unchecked(X(1)).expect("Missing X(1)");
+        assert!((x0.0 - VectorVar2::new(1.0, 1.0).0).norm() < 1e-4);
+        assert!((x1.0 - VectorVar2::new(3.0, 4.0).0).norm() < 1e-4);
+    }
+
+    // Loop of poses, poorly initialized along a line
+    fn circle() -> (Graph, Values) {
+        let mut graph = Graph::new();
+        let mut values = Values::new();
+        let res = PriorResidual::new(SE2::identity());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let n = 8;
+        for i in 0..n {
+            let delta = SE2::new(std::f64::consts::TAU as dtype / n as dtype, 1.0, 0.0);
+            let res = BetweenResidual::new(delta);
+            let factor = FactorBuilder::new2_unchecked(res, X(i), X((i + 1) % n)).build();
+            graph.add_factor(factor);
+            values.insert_unchecked(X(i), SE2::new(-0.5 * i as dtype, i as dtype, 0.0));
+        }
+        (graph, values)
+    }
+
+    #[test]
+    fn line_search() {
+        for line_search in [LineSearch::Armijo, LineSearch::Cubic] {
+            let (graph, values) = circle();
+            let mut opt: GaussNewton = GaussNewton::new(graph);
+            opt.params_gn.line_search = line_search;
+            let (result, summary) = opt.optimize_with_summary(values);
+            assert!(result.is_ok());
+
+            let mut error_old = summary.error_initial;
+            for iter in summary.iterations.iter() {
+                assert!(iter.error <= error_old);
+                error_old = iter.error;
+            }
+            assert!(summary.error_final < summary.error_initial);
+        }
+    }
+
+    #[test]
+    fn line_search_failed() {
+        // On a linear problem, the full step only gets half the predicted
+        // decrease, so a strict enough condition can never be met
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(VectorVar2::new(1.0, 1.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+
+        let mut opt: GaussNewton = GaussNewton::new(graph);
+        opt.params_gn.line_search = LineSearch::Armijo;
+        opt.params_gn.armijo_c = 0.9;
+        opt.params_gn.step_min = 0.6;
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(matches!(result, Err(OptError::LineSearchFailed)));
+        assert_eq!(summary.termination, TerminationReason::LineSearchFailed);
+    }
+
+    #[test]
+    fn warm_start() {
+        let between = |i: u32| {
+            let res = BetweenResidual::new(VectorVar2::new(1.0, 0.1 * i as dtype));
+            FactorBuilder::new2_unchecked(res, X(i - 1), X(i)).build()
+        };
+
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(VectorVar2::identity());
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        for i in 1..3 {
+            graph.add_factor(between(i));
+            values.insert_unchecked(X(i), VectorVar2::identity());
+        }
+
+        let mut opt: GaussNewton = GaussNewton::new(graph);
+        let mut values = opt.optimize(values).expect("Optimization failed");
+
+        // Grow the problem, with a key that would sort first
+        let mut new_factors = Graph::new();
+        new_factors.add_factor(between(3));
+        let res = BetweenResidual::new(VectorVar2::new(1.0, 0.4));
+        new_factors.add_factor(FactorBuilder::new2_unchecked(res, X(3), L(0)).build());
+        let mut new_values = Values::new();
+        new_values.insert_unchecked(X(3), VectorVar2::identity());
+        new_values.insert_unchecked(L(0), VectorVar2::identity());
+        opt.add_factors(new_factors);
+        opt.add_values(&new_values);
+        for (key, value) in new_values {
+            values.entry(key).or_insert(value);
+        }
+        let values = opt.optimize(values).expect("Optimization failed");
+
+        // New variables are appended in insertion order, and the pattern matches
+        // a fresh one
+        let graph_order = opt.graph_order.get();
+        assert_eq!(graph_order.order.get(X(3)).expect("Missing X(3)").idx, 6);
+        assert_eq!(graph_order.order.get(L(0)).expect("Missing L(0)").idx, 8);
+        let fresh = opt.graph.sparsity_pattern(graph_order.order.clone());
+        assert_eq!(
+            graph_order.sparsity_pattern.col_ptrs(),
+            fresh.sparsity_pattern.col_ptrs()
+        );
+        assert_eq!(
+            graph_order.sparsity_pattern.row_indices(),
+            fresh.sparsity_pattern.row_indices()
+        );
+
+        let l0: &VectorVar2 = values.get_unchecked(L(0)).expect("Missing L(0)");
+        assert!((l0.0 - VectorVar2::new(4.0, 1.0).0).norm() < 1e-4);
+    }
 }
@@ -0,0 +1,242 @@
+use super::{GraphOptimizer, LevenMarquardt, OptError, OptResult};
+use crate::{
+    containers::{Graph, Values},
+    dtype,
+};
+
+/// Parameters for [Gnc]
+#[derive(Debug, Clone)]
+pub struct GncParams {
+    /// Factor to change $\mu$ by at each outer iteration
+    pub mu_factor: dtype,
+    /// Maximum number of outer iterations
+    pub max_iterations: usize,
+    /// Factors with a final weight below this are considered outliers
+    pub inlier_threshold: dtype,
+}
+
+impl Default for GncParams {
+    fn default() -> Self {
+        Self {
+            mu_factor: 1.4,
+            max_iterations: 100,
+            inlier_threshold: 0.5,
+        }
+    }
+}
+
+/// Graduated Non-Convexity optimizer
+///
+/// Wraps any [GraphOptimizer], defaulting to [LevenMarquardt], to remove the
+/// need for good initialization when rejecting outliers. All factors with a GNC
+/// compatible robust kernel (see [GncCost](crate::robust::GncCost)) start out
+/// with a convex surrogate of their kernel. After each run of the inner
+/// optimizer the kernels are annealed towards their original shape, until they
+/// have been reached. See "Graduated Non-Convexity for Robust Spatial
+/// Perception" by Yang et al. for more details.
+///
+/// After optimizing, the weight of each factor can be retrieved using
+/// [weights](Gnc::weights), where inliers will have a weight near 1 and
+/// outliers a weight near 0.
+///
+/// Gnc is a wrapper rather than an [Optimizer](super::Optimizer) itself, so
+/// has no summary or observers of its own. Each run of the inner optimizer
+/// uses the parameters and observers set on it through
+/// [optimizer](Gnc::optimizer), including its cancellation token, which stops
+/// GNC as a whole, and its `max_time`, which limits every run separately.
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{FactorBuilder, Graph, Values},
+///    optimizers::Gnc,
+///    residuals::PriorResidual,
+///    robust::GncTruncatedLeastSquares,
+///    traits::*,
+///    variables::VectorVar2,
+/// };
+/// # assign_symbols!(X: VectorVar2);
+/// let mut graph = Graph::new();
+/// for p in [(1.0, 2.0), (1.1, 2.1), (0.9, 1.9), (10.0, -5.0)] {
+///     let res = PriorResidual::new(VectorVar2::new(p.0, p.1));
+///     let factor = FactorBuilder::new1(res, X(0))
+///         .robust(GncTruncatedLeastSquares::new(1.0))
+///         .build();
+///     graph.add_factor(factor);
+/// }
+///
+/// let mut values = Values::new();
+/// values.insert(X(0), VectorVar2::identity());
+///
+/// let mut gnc: Gnc = Gnc::new(graph);
+/// let result = gnc.optimize(values).expect("Optimization failed");
+/// assert_eq!(gnc.outliers(), vec![3]);
+/// ```
+pub struct Gnc<O: GraphOptimizer = LevenMarquardt> {
+    optimizer: O,
+    /// Parameters for annealing
+    pub params: GncParams,
+    weights: Vec<dtype>,
+}
+
+impl<O: GraphOptimizer> Gnc<O> {
+    pub fn new(graph: Graph) -> Self {
+        Self {
+            optimizer: O::new(graph),
+            params: GncParams::default(),
+            weights: Vec::new(),
+        }
+    }
+
+    /// The underlying optimizer, can be used to change its parameters
+    pub fn optimizer(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+
+    pub fn graph(&self) -> &Graph {
+        self.optimizer.graph()
+    }
+
+    /// Robust weight of each factor after the last optimization, in the same
+    /// order as the graph
+    pub fn weights(&self) -> &[dtype] {
+        &self.weights
+    }
+
+    /// Index of each factor whose final weight is below the inlier threshold
+    pub fn outliers(&self) -> Vec<usize> {
+        self.weights
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w < self.params.inlier_threshold)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn optimize_inner(&mut self, values: Values) -> OptResult<Values> {
+        // Later runs may start at the minimum, where no step can decrease the error
+        let start = values.clone();
+        match self.optimizer.optimize(values) {
+            Err(OptError::FailedToStep) => {
+                log::warn!("Inner GNC optimizer failed to step, continuing anyway");
+                Ok(start)
+            }
+            Err(OptError::MaxIterations(values)) => {
+                log::warn!("Inner GNC optimizer hit max iterations, continuing anyway");
+                Ok(values)
+            }
+            result => result,
+        }
+    }
+
+    /// Run the inner optimizer while annealing all GNC kernels
+    pub fn optimize(&mut self, mut values: Values) -> OptResult<Values> {
+        // Start all kernels convex for the current residuals
+        let d2_max = self
+            .graph()
+            .iter()
+            .map(|f| f.norm2(&values))
+            .fold(0.0, dtype::max);
+        let mut num_gnc = 0;
+        for factor in self.optimizer.graph_mut().iter_mut() {
+            if let Some(gnc) = factor.robust_mut().gnc() {
+                gnc.init_mu(d2_max);
+                num_gnc += 1;
+            }
+        }
+        log::info!("Running GNC on {} factors", num_gnc);
+
+        let mut done = num_gnc == 0;
+        for i in 0..self.params.max_iterations {
+            values = self.optimize_inner(values)?;
+            self.weights = self.graph().iter().map(|f| f.weight(&values)).collect();
+            if done {
+                log::info!("GNC finished after {} iterations", i + 1);
+                return Ok(values);
+            }
+
+            done = true;
+            for factor in self.optimizer.graph_mut().iter_mut() {
+                if let Some(gnc) = factor.robust_mut().gnc() {
+                    done &= gnc.step_mu(self.params.mu_factor);
+                }
+            }
+        }
+
+        Err(OptError::MaxIterations(values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        optimizers::GaussNewton,
+        residuals::{BetweenResidual, PriorResidual},
+        robust::{GncGemanMcClure, GncTruncatedLeastSquares, RobustCost},
+        symbols::X,
+        variables::{Variable, VectorVar2},
+    };
+
+    fn graph(robust: impl RobustCost + Clone + 'static) -> Graph {
+        let mut graph = Graph::new();
+        let inliers = [(1.0, 2.0), (1.1, 2.0), (0.9, 2.0), (1.0, 2.1), (1.0, 1.9)];
+        let outliers = [(8.0, -6.0), (9.0, -7.0), (-10.0, 5.0)];
+        for p in inliers.iter().chain(outliers.iter()) {
+            let res = PriorResidual::new(VectorVar2::new(p.0, p.1));
+            let factor = FactorBuilder::new1_unchecked(res, X(0))
+                .robust(robust.clone())
+                .build();
+            graph.add_factor(factor);
+        }
+        // Make sure regular factors are left alone
+        let res = BetweenResidual::new(VectorVar2::new(1.0, 1.0));
+        graph.add_factor(FactorBuilder::new2_unchecked(res, X(0), X(1)).build());
+        graph
+    }
+
+    fn check<O: GraphOptimizer>(robust: impl RobustCost + Clone + 'static) {
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::identity());
+
+        let mut gnc = Gnc::<O>::new(graph(robust));
+        let values = gnc.optimize(values).expect("Optimization failed");
+
+        let x: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        assert_matrix_eq!(x.0, VectorVar2::new(1.0, 2.0).0, comp = abs, tol = 1e-2);
+        assert_eq!(gnc.outliers(), vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn tls() {
+        check::<GaussNewton>(GncTruncatedLeastSquares::new(1.0));
+        check::<LevenMarquardt>(GncTruncatedLeastSquares::new(1.0));
+    }
+
+    #[test]
+    fn geman_mcclure() {
+        check::<GaussNewton>(GncGemanMcClure::new(1.0));
+        check::<LevenMarquardt>(GncGemanMcClure::new(1.0));
+    }
+
+    #[test]
+    fn inner_fails_to_step() {
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::identity());
+        values.insert_unchecked(X(1), VectorVar2::identity());
+
+        // Without its vanished gradient check, LM fails to step from the
+        // minimum that later runs start at
+        let mut gnc: Gnc = Gnc::new(graph(GncTruncatedLeastSquares::new(1.0)));
+        gnc.optimizer().params_base.error_tol_absolute = -1.0;
+        let values = gnc.optimize(values).expect("Optimization failed");
+
+        let x: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        assert_matrix_eq!(x.0, VectorVar2::new(1.0, 2.0).0, comp = abs, tol = 1e-2);
+        assert_eq!(gnc.outliers(), vec![5, 6, 7]);
+    }
+}
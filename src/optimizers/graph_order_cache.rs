@@ -0,0 +1,59 @@
+use crate::containers::{Graph, GraphOrder, OrderingStrategy, Values};
+
+/// Variable ordering and sparsity pattern kept between optimizations
+///
+/// Shared by the batch optimizers so that a graph that has only been appended
+/// to, via [add_factors](Self::add_factors) and
+/// [add_values](Self::add_values), keeps its ordering and only extends the
+/// cached sparsity pattern on the next [init](Self::init). Since the pattern
+/// still changes, linear solvers redo their symbolic analysis.
+#[derive(Default)]
+pub(crate) struct GraphOrderCache {
+    graph_order: Option<GraphOrder>,
+    warm_start: bool,
+}
+
+impl GraphOrderCache {
+    /// Prepare the ordering for a new optimization
+    ///
+    /// Keeps the previous ordering if the problem has only been appended to,
+    /// otherwise computes a new one with `ordering`.
+    pub fn init(
+        &mut self,
+        graph: &Graph,
+        values: &Values,
+        ordering: &OrderingStrategy,
+    ) -> &GraphOrder {
+        let graph_order = match self.graph_order.take() {
+            Some(graph_order) if self.warm_start && graph_order.covers(values) => {
+                graph.extend_sparsity_pattern(graph_order)
+            }
+            _ => graph.sparsity_pattern(ordering.order(graph, values)),
+        };
+        self.warm_start = false;
+        self.graph_order.insert(graph_order)
+    }
+
+    /// The current ordering, panics if [init](Self::init) hasn't been called
+    pub fn get(&self) -> &GraphOrder {
+        self.graph_order.as_ref().expect("Missing graph order")
+    }
+
+    /// Forget the ordering, for when the graph may have changed arbitrarily
+    pub fn clear(&mut self) {
+        self.graph_order = None;
+    }
+
+    /// Mark that factors were appended to the graph
+    pub fn add_factors(&mut self) {
+        self.warm_start = self.graph_order.is_some();
+    }
+
+    /// Append new variables to the end of the ordering
+    pub fn add_values(&mut self, values: &Values) {
+        if let Some(graph_order) = self.graph_order.as_mut() {
+            graph_order.add_values(values);
+            self.warm_start = true;
+        }
+    }
+}
@@ -0,0 +1,748 @@
+use faer_ext::{IntoFaer, IntoNalgebra};
+use foldhash::{HashMap, HashSet};
+
+use super::OptError;
+use crate::{
+    containers::{Graph, Key, Values},
+    dtype,
+    linalg::{MatrixBlock, MatrixX, VectorX},
+    linear::{LinearFactor, LinearSolverError},
+};
+
+/// Parameters for [Isam2]
+pub struct Isam2Params {
+    /// Variables whose update exceeds this (in the infinity norm) are
+    /// relinearized
+    pub relinearize_threshold: dtype,
+    /// Only check for relinearization every `relinearize_skip` updates
+    pub relinearize_skip: usize,
+    /// Back substitution stops descending the tree once updates change by
+    /// less than this (in the infinity norm)
+    pub wildfire_threshold: dtype,
+}
+
+impl Default for Isam2Params {
+    fn default() -> Self {
+        Self {
+            relinearize_threshold: 0.1,
+            relinearize_skip: 1,
+            wildfire_threshold: 1e-3,
+        }
+    }
+}
+
+/// Summary of the work done in a single [Isam2::update]
+#[derive(Debug, Clone)]
+pub struct Isam2Update {
+    /// Number of variables that were relinearized
+    pub relinearized: usize,
+    /// Number of cliques that were re-eliminated
+    pub eliminated: usize,
+    /// Number of cliques whose update was recomputed
+    pub back_substituted: usize,
+}
+
+/// A single clique of the Bayes tree
+///
+/// Each clique holds one frontal variable and the conditional
+/// $R \Delta_f + S \Delta_s = d$ on its separator, along with the marginal
+/// factor on the separator that was passed to its parent.
+struct Clique {
+    separator: Vec<Key>,
+    conditional: MatrixBlock,
+    d: VectorX,
+    marginal: Option<LinearFactor>,
+    parent: Option<Key>,
+    children: Vec<Key>,
+}
+
+// Where a linear factor waiting to be eliminated comes from
+enum Source {
+    Factor(usize),
+    Marginal(Key),
+}
+
+/// Incremental smoothing and mapping optimizer
+///
+/// Rather than relinearizing and refactoring the whole problem each time, this
+/// keeps the eliminated graph as a Bayes tree and only touches the cliques
+/// affected by an [update](Isam2::update). New factors and variables mark
+/// their keys, as do variables whose update has grown past
+/// `relinearize_threshold`. The cliques of marked keys and all their ancestors
+/// are removed and re-eliminated using dense QR, while the untouched subtrees
+/// below them contribute their cached marginals. Back substitution then starts
+/// from the re-eliminated cliques and only descends into children whose
+/// separator changed by more than `wildfire_threshold`.
+///
+/// Variables are eliminated in the order they were first added, so it's best
+/// to add them in the order they are observed (as in odometry).
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{FactorBuilder, Graph, Values},
+///    optimizers::Isam2,
+///    residuals::{BetweenResidual, PriorResidual},
+///    traits::*,
+///    variables::SE2,
+/// };
+/// # assign_symbols!(X: SE2);
+/// let mut isam = Isam2::new();
+///
+/// let mut graph = Graph::new();
+/// graph.add_factor(FactorBuilder::new1(PriorResidual::new(SE2::identity()), X(0)).build());
+/// let mut values = Values::new();
+/// values.insert(X(0), SE2::identity());
+/// isam.update(graph, values).expect("Update failed");
+///
+/// let mut graph = Graph::new();
+/// let delta = SE2::new(0.1, 1.0, 0.0);
+/// graph.add_factor(FactorBuilder::new2(BetweenResidual::new(delta), X(0), X(1)).build());
+/// let mut values = Values::new();
+/// values.insert(X(1), SE2::new(0.0, 0.9, 0.1));
+/// isam.update(graph, values).expect("Update failed");
+///
+/// let estimate = isam.estimate();
+/// ```
+pub struct Isam2 {
+    graph: Graph,
+    // Linearization point
+    values: Values,
+    // Each factor in graph linearized around values
+    linear: Vec<LinearFactor>,
+    factors_of_key: HashMap<Key, Vec<usize>>,
+    ordering: Vec<Key>,
+    position: HashMap<Key, usize>,
+    cliques: HashMap<Key, Clique>,
+    delta: HashMap<Key, VectorX>,
+    // Keys whose update has exceeded the relinearization threshold
+    moved: HashSet<Key>,
+    updates: usize,
+    /// Parameters for relinearization
+    pub params: Isam2Params,
+}
+
+impl Default for Isam2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Isam2 {
+    pub fn new() -> Self {
+        Self {
+            graph: Graph::new(),
+            values: Values::new(),
+            linear: Vec::new(),
+            factors_of_key: HashMap::default(),
+            ordering: Vec::new(),
+            position: HashMap::default(),
+            cliques: HashMap::default(),
+            delta: HashMap::default(),
+            moved: HashSet::default(),
+            updates: 0,
+            params: Isam2Params::default(),
+        }
+    }
+
+    /// All factors added so far
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// The current linearization point
+    pub fn linearization_point(&self) -> &Values {
+        &self.values
+    }
+
+    /// The current estimate, ie the linearization point updated by the
+    /// solution of the Bayes tree
+    pub fn estimate(&self) -> Values {
+        let mut values = self.values.clone();
+        for (key, delta) in self.delta.iter() {
+            if let Some(v) = values.get_raw_mut(*key) {
+                v.oplus_mut(delta.as_view());
+            }
+        }
+        values
+    }
+
+    /// Add new factors and variables, then update the estimate
+    ///
+    /// Values for keys that are already present are ignored, new ones are added
+    /// to the ordering in the order they were inserted. If a new factor
+    /// references a key without a value, [MissingKey](OptError::MissingKey) is
+    /// returned and nothing is added. If the system can't be eliminated (ie
+    /// it's underconstrained), [InvalidSystem](OptError::InvalidSystem) is
+    /// returned; the factors and values are kept, and the whole tree is rebuilt
+    /// on the next update.
+    pub fn update(
+        &mut self,
+        new_factors: Graph,
+        new_values: Values,
+    ) -> Result<Isam2Update, OptError<Values>> {
+        // Make sure every factor can be linearized before changing anything
+        for factor in new_factors.iter() {
+            for key in factor.keys() {
+                if !self.position.contains_key(key) && new_values.get_raw(*key).is_none() {
+                    return Err(OptError::MissingKey(*key));
+                }
+            }
+        }
+
+        let mut marked = HashSet::default();
+
+        // Add new variables to the end of the ordering
+        for (key, value) in new_values {
+            if !self.position.contains_key(&key) {
+                self.values.entry(key).or_insert(value);
+                self.position.insert(key, self.ordering.len());
+                self.ordering.push(key);
+                marked.insert(key);
+            }
+        }
+
+        // Relinearize any variables that have moved too far
+        let mut relinearized = Vec::new();
+        if self.updates % self.params.relinearize_skip.max(1) == 0 {
+            for key in self.moved.drain() {
+                let delta = self.delta.get_mut(&key).expect("Key missing in delta");
+                if delta.amax() > self.params.relinearize_threshold {
+                    self.values
+                        .get_raw_mut(key)
+                        .expect("Key missing in values")
+                        .oplus_mut(delta.as_view());
+                    delta.fill(0.0);
+                    relinearized.push(key);
+                }
+            }
+        }
+        self.updates += 1;
+
+        let mut relinearize_factors = HashSet::default();
+        for key in relinearized.iter() {
+            for &idx in self.factors_of_key.get(key).into_iter().flatten() {
+                relinearize_factors.insert(idx);
+            }
+        }
+        for idx in relinearize_factors {
+            let factor = self.graph.get(idx).expect("Factor missing in graph");
+            self.linear[idx] = factor.linearize(&self.values);
+            marked.extend(factor.keys().iter().copied());
+        }
+
+        // Add new factors
+        for factor in new_factors {
+            let idx = self.linear.len();
+            for key in factor.keys() {
+                self.factors_of_key.entry(*key).or_default().push(idx);
+                marked.insert(*key);
+            }
+            self.linear.push(factor.linearize(&self.values));
+            self.graph.add_factor(factor);
+        }
+
+        // Anything without a clique has to be eliminated as well
+        for key in self.ordering.iter() {
+            if !self.cliques.contains_key(key) {
+                marked.insert(*key);
+            }
+        }
+
+        // Remove marked cliques and everything above them
+        let mut removed = HashSet::default();
+        for key in marked {
+            let mut current = Some(key);
+            while let Some(k) = current {
+                if !removed.insert(k) {
+                    break;
+                }
+                current = self.cliques.get(&k).and_then(|c| c.parent);
+            }
+        }
+        let mut removed = removed.into_iter().collect::<Vec<_>>();
+        removed.sort_by_key(|k| self.position[k]);
+
+        // Collect what has to be eliminated for each removed key
+        let mut pending: HashMap<Key, Vec<Source>> = HashMap::default();
+        let mut orphans = Vec::new();
+        for key in removed.iter() {
+            for &idx in self.factors_of_key.get(key).into_iter().flatten() {
+                if self.first_key(&self.linear[idx].keys) == *key {
+                    pending.entry(*key).or_default().push(Source::Factor(idx));
+                }
+            }
+            if let Some(clique) = self.cliques.remove(key) {
+                for child in clique.children {
+                    if self.cliques.contains_key(&child) {
+                        pending
+                            .entry(*key)
+                            .or_default()
+                            .push(Source::Marginal(child));
+                        orphans.push((child, *key));
+                    }
+                }
+            }
+        }
+
+        // Re-eliminate the top of the tree
+        let mut eliminated: HashMap<Key, Clique> = HashMap::default();
+        for key in removed.iter() {
+            let sources = pending.remove(key).unwrap_or_default();
+            let factors = sources
+                .iter()
+                .filter_map(|s| match s {
+                    Source::Factor(idx) => Some(&self.linear[*idx]),
+                    Source::Marginal(k) => eliminated
+                        .get(k)
+                        .or_else(|| self.cliques.get(k))
+                        .and_then(|c| c.marginal.as_ref()),
+                })
+                .collect::<Vec<_>>();
+
+            let clique = match self.eliminate(*key, &factors) {
+                Some(c) => c,
+                None => {
+                    log::warn!("Failed to eliminate {:?}, system is underconstrained", key);
+                    self.cliques.clear();
+                    return Err(OptError::InvalidSystem {
+                        error: LinearSolverError::NotPositiveDefinite(None),
+                        key: Some(*key),
+                    });
+                }
+            };
+
+            if let Some(parent) = clique.parent {
+                pending
+                    .entry(parent)
+                    .or_default()
+                    .push(Source::Marginal(*key));
+            }
+            eliminated.insert(*key, clique);
+        }
+
+        // Hook everything back into the tree
+        let links = eliminated
+            .iter()
+            .filter_map(|(k, c)| c.parent.map(|p| (*k, p)))
+            .chain(orphans)
+            .collect::<Vec<_>>();
+        for (child, parent) in links {
+            eliminated
+                .get_mut(&parent)
+                .expect("Parent clique missing")
+                .children
+                .push(child);
+        }
+        self.cliques.extend(eliminated);
+
+        let back_substituted = self.back_substitute(&removed);
+
+        Ok(Isam2Update {
+            relinearized: relinearized.len(),
+            eliminated: removed.len(),
+            back_substituted,
+        })
+    }
+
+    // The key that's eliminated first
+    fn first_key(&self, keys: &[Key]) -> Key {
+        *keys
+            .iter()
+            .min_by_key(|k| self.position[k])
+            .expect("Factor has no keys")
+    }
+
+    fn dim(&self, key: Key) -> usize {
+        self.values
+            .get_raw(key)
+            .expect("Key missing in values")
+            .dim()
+    }
+
+    /// Eliminate a single variable from a set of linear factors via dense QR
+    ///
+    /// Returns None if the variable isn't fully constrained.
+    fn eliminate(&self, frontal: Key, factors: &[&LinearFactor]) -> Option<Clique> {
+        // Find the separator
+        let mut separator = factors
+            .iter()
+            .flat_map(|f| f.keys.iter().copied())
+            .filter(|k| *k != frontal)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        separator.sort_by_key(|k| self.position[k]);
+
+        // Column offsets of each variable, frontal first
+        let mut offsets = HashMap::default();
+        let mut idx = Vec::new();
+        let mut cols = 0;
+        for key in std::iter::once(&frontal).chain(separator.iter()) {
+            offsets.insert(*key, cols);
+            idx.push(cols);
+            cols += self.dim(*key);
+        }
+        let dim_frontal = idx.get(1).copied().unwrap_or(cols);
+
+        // Stack everything into [A | b]
+        let rows = factors.iter().map(|f| f.dim_out()).sum::<usize>();
+        if rows < dim_frontal {
+            return None;
+        }
+        let mut ab = MatrixX::zeros(rows, cols + 1);
+        let mut row = 0;
+        for f in factors {
+            for (i, key) in f.keys.iter().enumerate() {
+                let block = f.a.get_block(i);
+                ab.view_mut((row, offsets[key]), block.shape())
+                    .copy_from(&block);
+            }
+            ab.view_mut((row, cols), (f.dim_out(), 1)).copy_from(&f.b);
+            row += f.dim_out();
+        }
+
+        let r = ab
+            .view_range(.., ..)
+            .into_faer()
+            .qr()
+            .compute_thin_r()
+            .as_ref()
+            .into_nalgebra()
+            .clone_owned();
+
+        // Make sure the frontal variable is actually constrained
+        let scale = r.view((0, 0), (dim_frontal, dim_frontal)).amax();
+        let tol = dtype::EPSILON * scale * (rows as dtype);
+        if (0..dim_frontal).any(|i| r[(i, i)].abs() <= tol) {
+            return None;
+        }
+
+        let conditional = MatrixBlock::new(r.view((0, 0), (dim_frontal, cols)).clone_owned(), idx);
+        let d = r.view((0, cols), (dim_frontal, 1)).column(0).clone_owned();
+
+        let marginal = if separator.is_empty() {
+            None
+        } else {
+            let rows_marginal = r.nrows().min(cols).saturating_sub(dim_frontal);
+            let a = r
+                .view(
+                    (dim_frontal, dim_frontal),
+                    (rows_marginal, cols - dim_frontal),
+                )
+                .clone_owned();
+            let b = r
+                .view((dim_frontal, cols), (rows_marginal, 1))
+                .column(0)
+                .clone_owned();
+            let idx = separator.iter().map(|k| offsets[k] - dim_frontal).collect();
+            Some(LinearFactor::new(
+                separator.clone(),
+                MatrixBlock::new(a, idx),
+                b,
+            ))
+        };
+
+        Some(Clique {
+            parent: separator.first().copied(),
+            separator,
+            conditional,
+            d,
+            marginal,
+            children: Vec::new(),
+        })
+    }
+
+    // Solve for updates from the re-eliminated cliques down, only continuing
+    // into subtrees whose separator changed. Returns the number of cliques
+    // solved.
+    fn back_substitute(&mut self, replaced: &[Key]) -> usize {
+        let replaced = replaced.iter().copied().collect::<HashSet<_>>();
+        let mut changed = HashSet::default();
+        let mut solved = 0;
+
+        // All ancestors of a replaced clique are replaced, so this covers the
+        // top of every affected tree
+        let mut stack = replaced
+            .iter()
+            .filter(|k| self.cliques[k].parent.is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        while let Some(key) = stack.pop() {
+            let clique = &self.cliques[&key];
+            let is_replaced = replaced.contains(&key);
+            if !is_replaced && !clique.separator.iter().any(|k| changed.contains(k)) {
+                continue;
+            }
+
+            let mut rhs = clique.d.clone();
+            for (i, sep) in clique.separator.iter().enumerate() {
+                rhs -= clique.conditional.mul(i + 1, self.delta[sep].as_view());
+            }
+            let delta = clique
+                .conditional
+                .get_block(0)
+                .solve_upper_triangular(&rhs)
+                .expect("Clique conditional is singular");
+            solved += 1;
+
+            let diff = self
+                .delta
+                .get(&key)
+                .map_or(dtype::INFINITY, |old| (&delta - old).amax());
+            let is_changed = diff > self.params.wildfire_threshold;
+            if is_changed {
+                changed.insert(key);
+            }
+            // Replaced cliques are always visited, since their conditional
+            // may be different even if the update isn't
+            stack.extend(
+                clique
+                    .children
+                    .iter()
+                    .filter(|k| is_changed || replaced.contains(k))
+                    .copied(),
+            );
+            if is_replaced || is_changed {
+                if delta.amax() > self.params.relinearize_threshold {
+                    self.moved.insert(key);
+                } else {
+                    self.moved.remove(&key);
+                }
+                self.delta.insert(key, delta);
+            }
+        }
+
+        solved
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        assign_symbols,
+        containers::FactorBuilder,
+        noise::GaussianNoise,
+        residuals::{BetweenResidual, PriorResidual},
+        variables::{Variable, VectorVar2, SE2},
+    };
+
+    assign_symbols!(X: SE2; V: VectorVar2);
+
+    fn odometry(i: u32) -> SE2 {
+        SE2::new(0.1 * i as dtype, 1.0, 0.1)
+    }
+
+    #[test]
+    fn odometry_chain() {
+        let mut isam = Isam2::new();
+        isam.params.relinearize_threshold = 0.0;
+        let noise = GaussianNoise::<3>::from_scalar_sigma(0.1);
+
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1(PriorResidual::new(SE2::identity()), X(0))
+                .noise(noise.clone())
+                .build(),
+        );
+        let mut values = Values::new();
+        values.insert(X(0), SE2::identity());
+        isam.update(graph, values).expect("Update failed");
+
+        let mut truth = vec![SE2::identity()];
+        for i in 1..10 {
+            truth.push(truth[i as usize - 1].compose(&odometry(i)));
+
+            let mut graph = Graph::new();
+            graph.add_factor(
+                FactorBuilder::new2(BetweenResidual::new(odometry(i)), X(i - 1), X(i))
+                    .noise(noise.clone())
+                    .build(),
+            );
+            // Initialize with a poor guess to force some relinearization
+            let mut values = Values::new();
+            values.insert(X(i), SE2::new(0.0, i as dtype, 0.0));
+            isam.update(graph, values).expect("Update failed");
+        }
+
+        // A few more updates to let relinearization converge
+        for _ in 0..5 {
+            isam.update(Graph::new(), Values::new())
+                .expect("Update failed");
+        }
+
+        let estimate = isam.estimate();
+        for (i, exp) in truth.iter().enumerate() {
+            let got: &SE2 = estimate.get(X(i as u32)).expect("Missing X");
+            assert_matrix_eq!(got.ominus(exp), VectorX::zeros(3), comp = abs, tol = 1e-6);
+        }
+    }
+
+    #[test]
+    fn only_affected_cliques() {
+        let mut isam = Isam2::new();
+        // Linear problem, so relinearization is never needed
+        isam.params.relinearize_threshold = dtype::INFINITY;
+
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1(PriorResidual::new(VectorVar2::new(1.0, 2.0)), V(0)).build(),
+        );
+        let mut values = Values::new();
+        values.insert(V(0), VectorVar2::identity());
+        let info = isam.update(graph, values).expect("Update failed");
+        assert_eq!(info.eliminated, 1);
+
+        for i in 1..10 {
+            let mut graph = Graph::new();
+            graph.add_factor(
+                FactorBuilder::new2(
+                    BetweenResidual::new(VectorVar2::new(1.0, 0.0)),
+                    V(i - 1),
+                    V(i),
+                )
+                .build(),
+            );
+            let mut values = Values::new();
+            values.insert(V(i), VectorVar2::identity());
+            let info = isam.update(graph, values).expect("Update failed");
+            // Only the previous and new variable need to be touched
+            assert_eq!(info.eliminated, 2);
+            // And the measurement agrees with the current estimate, so
+            // nothing below them changes
+            assert_eq!(info.back_substituted, 2);
+        }
+
+        // Closing a loop touches everything between
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new2(BetweenResidual::new(VectorVar2::new(5.0, 0.0)), V(4), V(9))
+                .build(),
+        );
+        let info = isam.update(graph, Values::new()).expect("Update failed");
+        assert_eq!(info.eliminated, 6);
+
+        let estimate = isam.estimate();
+        let v9: &VectorVar2 = estimate.get(V(9)).expect("Missing V(9)");
+        assert_matrix_eq!(v9.0, VectorVar2::new(10.0, 2.0).0, comp = abs, tol = 1e-6);
+    }
+
+    #[test]
+    fn wildfire() {
+        let mut isam = Isam2::new();
+        isam.params.relinearize_threshold = dtype::INFINITY;
+
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1(PriorResidual::new(VectorVar2::new(1.0, 2.0)), V(0)).build(),
+        );
+        let mut values = Values::new();
+        for i in 0..10 {
+            values.insert(V(i), VectorVar2::identity());
+        }
+        for i in 1..10 {
+            graph.add_factor(
+                FactorBuilder::new2(
+                    BetweenResidual::new(VectorVar2::new(1.0, 0.0)),
+                    V(i - 1),
+                    V(i),
+                )
+                .build(),
+            );
+        }
+        isam.update(graph, values).expect("Update failed");
+
+        // A loop closure that disagrees moves every variable but the first,
+        // so the whole tree is solved again
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new2(BetweenResidual::new(VectorVar2::new(10.0, 0.0)), V(0), V(9))
+                .build(),
+        );
+        let info = isam.update(graph, Values::new()).expect("Update failed");
+        assert_eq!(info.back_substituted, 10);
+
+        let estimate = isam.estimate();
+        let v9: &VectorVar2 = estimate.get(V(9)).expect("Missing V(9)");
+        assert_matrix_eq!(v9.0, VectorVar2::new(10.9, 2.0).0, comp = abs, tol = 1e-5);
+    }
+
+    #[test]
+    fn underconstrained() {
+        let mut isam = Isam2::new();
+
+        let mut values = Values::new();
+        values.insert(V(0), VectorVar2::identity());
+        values.insert(V(1), VectorVar2::identity());
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new2(BetweenResidual::new(VectorVar2::new(1.0, 0.0)), V(0), V(1))
+                .build(),
+        );
+        assert!(matches!(
+            isam.update(graph, values),
+            Err(OptError::InvalidSystem { key: Some(_), .. })
+        ));
+
+        // Adding a prior fixes it
+        let mut graph = Graph::new();
+        graph.add_factor(
+            FactorBuilder::new1(PriorResidual::new(VectorVar2::new(1.0, 2.0)), V(0)).build(),
+        );
+        isam.update(graph, Values::new()).expect("Update failed");
+
+        let estimate = isam.estimate();
+        let v1: &VectorVar2 = estimate.get(V(1)).expect("Missing V(1)");
+        assert_matrix_eq!(v1.0, VectorVar2::new(2.0, 2.0).0, comp = abs, tol = 1e-6);
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut isam = Isam2::new();
+
+        // Symbols aren't sorted, new keys are appended as inserted
+        let mut values = Values::new();
+        values.insert(X(0), SE2::identity());
+        values.insert(V(0), VectorVar2::identity());
+        let mut graph = Graph::new();
+        graph.add_factor(FactorBuilder::new1(PriorResidual::new(SE2::identity()), X(0)).build());
+        graph.add_factor(
+            FactorBuilder::new1(PriorResidual::new(VectorVar2::identity()), V(0)).build(),
+        );
+        isam.update(graph, values).expect("Update failed");
+        assert_eq!(isam.ordering, vec![X(0).into(), V(0).into()]);
+    }
+
+    #[test]
+    fn missing_key() {
+        let mut isam = Isam2::new();
+
+        let graph = || {
+            let mut graph = Graph::new();
+            let res = PriorResidual::new(VectorVar2::new(1.0, 2.0));
+            graph.add_factor(FactorBuilder::new1(res, V(0)).build());
+            let res = BetweenResidual::new(VectorVar2::new(1.0, 0.0));
+            graph.add_factor(FactorBuilder::new2(res, V(0), V(1)).build());
+            graph
+        };
+        let mut values = Values::new();
+        values.insert(V(0), VectorVar2::identity());
+        assert!(matches!(
+            isam.update(graph(), values.clone()),
+            Err(OptError::MissingKey(key)) if key == V(1).into()
+        ));
+
+        // Nothing was added, so the update can be retried
+        assert!(isam.graph().is_empty());
+        assert!(isam.linearization_point().is_empty());
+        values.insert(V(1), VectorVar2::identity());
+        isam.update(graph(), values).expect("Update failed");
+
+        let estimate = isam.estimate();
+        let v1: &VectorVar2 = estimate.get(V(1)).expect("Missing V(1)");
+        assert_matrix_eq!(v1.0, VectorVar2::new(2.0, 2.0).0, comp = abs, tol = 1e-6);
+    }
+}
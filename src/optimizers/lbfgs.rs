@@ -0,0 +1,529 @@
+use std::{collections::VecDeque, time::Instant};
+
+use super::{
+    gauss_newton::interpolate, IterationRecord, IterationSummary, ObserverControl, OptError,
+    OptObserverVec, OptParams, OptResult, Optimizer,
+};
+use crate::{
+    containers::{Factor, Graph, Key, Values, ValuesOrder},
+    dtype,
+    linalg::{DiffResult, VectorX},
+    linear::LinearValues,
+};
+
+/// A general scalar cost over a handful of variables
+///
+/// Unlike a [Residual](crate::residuals::Residual), the cost doesn't need to
+/// be a sum of squares, so things like entropies or log-sum-exp terms can be
+/// used. The gradient is with respect to the tangent space of each variable,
+/// stacked in the order of [keys](ScalarCost::keys), and is most easily
+/// computed using one of the [gradient](crate::linalg::Diff) methods.
+///
+/// Every [Factor] is also a scalar cost, with its error as the cost.
+pub trait ScalarCost {
+    /// Variables the cost depends on
+    fn keys(&self) -> &[Key];
+
+    /// Value and gradient of the cost
+    fn gradient(&self, values: &Values) -> DiffResult<dtype, VectorX>;
+
+    /// Value of the cost, optional
+    fn cost(&self, values: &Values) -> dtype {
+        self.gradient(values).value
+    }
+}
+
+impl ScalarCost for Factor {
+    fn keys(&self) -> &[Key] {
+        Factor::keys(self)
+    }
+
+    fn gradient(&self, values: &Values) -> DiffResult<dtype, VectorX> {
+        // Linearized system is A = sqrt(w) W J, b = -sqrt(w) W r
+        let linear = self.linearize(values);
+        DiffResult {
+            value: self.error(values),
+            diff: -(linear.a.mat().transpose() * &linear.b),
+        }
+    }
+
+    fn cost(&self, values: &Values) -> dtype {
+        self.error(values)
+    }
+}
+
+/// L-BFGS specific parameters
+#[derive(Debug, Clone)]
+pub struct LbfgsParams {
+    /// Number of previous steps used to approximate the inverse Hessian
+    pub memory: usize,
+    /// Fraction of the predicted decrease required by the Armijo condition
+    pub armijo_c: dtype,
+    /// Smallest step length to try before giving up
+    pub step_min: dtype,
+}
+
+impl Default for LbfgsParams {
+    fn default() -> Self {
+        Self {
+            memory: 10,
+            armijo_c: 1e-4,
+            step_min: 1e-10,
+        }
+    }
+}
+
+// A previous step s and change in gradient y, with rho = 1 / s^T y
+struct Correction {
+    s: VectorX,
+    y: VectorX,
+    rho: dtype,
+}
+
+/// The L-BFGS optimizer
+///
+/// Minimizes a sum of general [ScalarCost]s, rather than a sum of squares.
+/// Each step builds a search direction from the gradient and the last few
+/// steps via the two-loop recursion, then backtracks along it until the
+/// Armijo condition is met. Steps are applied with
+/// [oplus](crate::variables::Variable::oplus), and gradients from different
+/// iterates are compared directly in the tangent space, ie vector transport is
+/// the identity. See "Numerical Optimization" by Nocedal and Wright, chapter 7
+/// for more details.
+///
+/// Costs are added using [add_cost](Lbfgs::add_cost). Parameters can be
+/// modified using the `params` and `params_lbfgs` fields, and observers add
+/// using `observers`. Since the costs may be negative, the default
+/// [error_tol](OptParams::error_tol) is disabled.
+///
+/// ```
+/// # use factrs::{
+///    assign_symbols,
+///    containers::{Key, Values},
+///    dtype,
+///    linalg::{Const, DiffResult, ForwardProp, Numeric, VectorX},
+///    optimizers::{Lbfgs, ScalarCost},
+///    traits::*,
+///    variables::VectorVar2,
+/// };
+/// # assign_symbols!(X: VectorVar2);
+/// // Smooth maximum of the coordinates, log(exp(x) + exp(y)), plus a
+/// // regularizer pulling it towards (1, 2)
+/// fn log_sum_exp<T: Numeric>(x: VectorVar2<T>) -> T {
+///     let lse = (x[0].exp() + x[1].exp()).ln();
+///     lse + (x[0] - 1.0).powi(2) + (x[1] - 2.0).powi(2)
+/// }
+///
+/// struct LogSumExp(Key);
+///
+/// impl ScalarCost for LogSumExp {
+///     fn keys(&self) -> &[Key] {
+///         std::slice::from_ref(&self.0)
+///     }
+///
+///     fn gradient(&self, values: &Values) -> DiffResult<dtype, VectorX> {
+///         let x: &VectorVar2 = values.get_unchecked(self.0).expect("Missing key");
+///         ForwardProp::<Const<2>>::gradient_1(log_sum_exp, x)
+///     }
+/// }
+///
+/// let mut values = Values::new();
+/// values.insert(X(0), VectorVar2::identity());
+///
+/// let mut opt = Lbfgs::new();
+/// opt.add_cost(LogSumExp(X(0).into()));
+/// let result = opt.optimize(values).expect("Optimization failed");
+/// ```
+pub struct Lbfgs {
+    costs: Vec<Box<dyn ScalarCost>>,
+    /// Basic parameters for the optimizer
+    pub params: OptParams,
+    /// L-BFGS specific parameters
+    pub params_lbfgs: LbfgsParams,
+    /// Observers for the optimizer
+    pub observers: OptObserverVec<Values>,
+    // For caching computation between steps
+    order: Option<ValuesOrder>,
+    history: VecDeque<Correction>,
+    gradient: Option<VectorX>,
+    summary: IterationSummary,
+}
+
+impl Lbfgs {
+    pub fn new() -> Self {
+        Self {
+            costs: Vec::new(),
+            params: OptParams {
+                error_tol: dtype::NEG_INFINITY,
+                ..Default::default()
+            },
+            params_lbfgs: LbfgsParams::default(),
+            observers: OptObserverVec::default(),
+            order: None,
+            history: VecDeque::new(),
+            gradient: None,
+            summary: IterationSummary::default(),
+        }
+    }
+
+    /// Create an optimizer with each factor of a graph as a cost
+    pub fn from_graph(graph: Graph) -> Self {
+        let mut opt = Self::new();
+        for factor in graph {
+            opt.add_cost(factor);
+        }
+        opt
+    }
+
+    pub fn add_cost(&mut self, cost: impl ScalarCost + 'static) {
+        self.costs.push(Box::new(cost));
+    }
+
+    pub fn costs(&self) -> &[Box<dyn ScalarCost>] {
+        &self.costs
+    }
+
+    // Gradient of all costs, skipping any fixed variables
+    fn gradient(&self, values: &Values, order: &ValuesOrder) -> VectorX {
+        let mut gradient = VectorX::zeros(order.dim());
+        for cost in self.costs.iter() {
+            let diff = cost.gradient(values).diff;
+            let mut col = 0;
+            for key in cost.keys() {
+                let dim = values.get_raw(*key).expect("Key missing in values").dim();
+                if let Some(idx) = order.get(*key) {
+                    let mut block = gradient.rows_mut(idx.idx, dim);
+                    block += diff.rows(col, dim);
+                }
+                col += dim;
+            }
+        }
+        gradient
+    }
+
+    // Two-loop recursion, applying the approximate inverse Hessian to the
+    // negative gradient
+    fn direction(&self, gradient: &VectorX) -> VectorX {
+        let mut q = gradient.clone();
+        let mut alphas = Vec::with_capacity(self.history.len());
+        for c in self.history.iter().rev() {
+            let alpha = c.rho * c.s.dot(&q);
+            q.axpy(-alpha, &c.y, 1.0);
+            alphas.push(alpha);
+        }
+
+        if let Some(c) = self.history.back() {
+            q *= c.s.dot(&c.y) / c.y.norm_squared();
+        }
+
+        for (c, alpha) in self.history.iter().zip(alphas.iter().rev()) {
+            let beta = c.rho * c.y.dot(&q);
+            q.axpy(alpha - beta, &c.s, 1.0);
+        }
+        -q
+    }
+
+    /// Find a step length along `delta` satisfying the Armijo condition
+    ///
+    /// `slope` is the directional derivative of the cost along `delta`.
+    fn line_search(
+        &self,
+        values: &Values,
+        order: &ValuesOrder,
+        delta: &VectorX,
+        slope: dtype,
+        alpha_init: dtype,
+    ) -> Result<dtype, OptError<Values>> {
+        // Not enough predicted decrease to bother searching
+        if -slope * alpha_init <= self.params.error_tol_absolute {
+            return Ok(alpha_init);
+        }
+
+        let error = |alpha: dtype| {
+            let mut values = values.clone();
+            let dx = LinearValues::from_order_and_vector(order.clone(), delta * alpha);
+            values.oplus_mut(&dx);
+            self.error(&values)
+        };
+
+        let error_0 = self.error(values);
+        let mut alpha = alpha_init;
+        let mut error_alpha = error(alpha);
+        let mut prev = None;
+        while error_alpha.is_nan()
+            || error_alpha > error_0 + self.params_lbfgs.armijo_c * alpha * slope
+        {
+            let next = interpolate(error_0, slope, (alpha, error_alpha), prev);
+            prev = Some((alpha, error_alpha));
+            alpha = next;
+
+            if alpha < self.params_lbfgs.step_min {
+                return Err(OptError::LineSearchFailed);
+            }
+            error_alpha = error(alpha);
+        }
+
+        Ok(alpha)
+    }
+}
+
+impl Default for Lbfgs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Optimizer for Lbfgs {
+    type Input = Values;
+
+    fn error(&self, values: &Values) -> dtype {
+        self.costs.iter().map(|c| c.cost(values)).sum()
+    }
+
+    fn params(&self) -> &OptParams {
+        &self.params
+    }
+
+    fn init(&mut self, values: &Values) {
+        self.order = Some(ValuesOrder::from_values(values));
+        self.history.clear();
+        self.gradient = None;
+    }
+
+    fn step_summary(&self) -> IterationSummary {
+        self.summary.clone()
+    }
+
+    fn notify(&self, values: &Values, mut record: IterationRecord) -> ObserverControl {
+        if self.observers.is_empty() {
+            return ObserverControl::Continue;
+        }
+        record.factor_errors = self.costs.iter().map(|c| c.cost(values)).collect();
+        self.observers.notify(values, &record)
+    }
+
+    fn step(&mut self, mut values: Values, _idx: usize) -> OptResult<Values> {
+        let order = self.order.take().expect("Missing values order");
+
+        // Gradient is left over from the previous step
+        let start = Instant::now();
+        let gradient = match self.gradient.take() {
+            Some(gradient) => gradient,
+            None => self.gradient(&values, &order),
+        };
+        self.summary.time_linearize = start.elapsed();
+
+        // Fall back to steepest descent if the direction isn't a descent one
+        let start = Instant::now();
+        let mut delta = self.direction(&gradient);
+        let mut slope = gradient.dot(&delta);
+        if slope.is_nan() || slope >= 0.0 {
+            log::debug!("L-BFGS direction isn't a descent direction, resetting memory");
+            self.history.clear();
+            delta = -&gradient;
+            slope = -gradient.norm_squared();
+        }
+
+        // Without any curvature information, start with a unit length step
+        let alpha_init = match self.history.is_empty() {
+            true => (1.0 / gradient.norm()).min(1.0),
+            false => 1.0,
+        };
+        let alpha = self.line_search(&values, &order, &delta, slope, alpha_init);
+        self.summary.time_solve = start.elapsed();
+        let alpha = match alpha {
+            Ok(alpha) => alpha,
+            Err(e) => {
+                self.order = Some(order);
+                return Err(e);
+            }
+        };
+
+        // Update the values
+        let start = Instant::now();
+        let s = delta * alpha;
+        self.summary.step_norm = s.norm();
+        let dx = LinearValues::from_order_and_vector(order.clone(), s.clone());
+        values.oplus_mut(&dx);
+
+        // Store the step, skipping it if the curvature condition fails
+        let gradient_new = self.gradient(&values, &order);
+        let y = &gradient_new - gradient;
+        let sy = s.dot(&y);
+        if sy > dtype::EPSILON * s.norm() * y.norm() {
+            if self.history.len() == self.params_lbfgs.memory {
+                self.history.pop_front();
+            }
+            self.history.push_back(Correction {
+                s,
+                y,
+                rho: 1.0 / sy,
+            });
+        }
+        self.gradient = Some(gradient_new);
+        self.order = Some(order);
+        self.summary.time_update = start.elapsed();
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use matrixcompare::assert_matrix_eq;
+
+    use super::*;
+    use crate::{
+        containers::FactorBuilder,
+        linalg::{Const, Diff, ForwardProp, Numeric, Vector3},
+        optimizers::{LevenMarquardt, OptObserver},
+        residuals::{BetweenResidual, PriorResidual},
+        symbols::X,
+        variables::{Variable, VectorVar2, SE2, SO3},
+    };
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
+
+    fn tight(opt: &mut Lbfgs) {
+        opt.params.error_tol_absolute = TOL * TOL;
+        opt.params.error_tol_relative = 0.0;
+    }
+
+    // Smooth approximation of |x - 1| + |y + 2|, which isn't a sum of squares
+    fn log_cosh<T: Numeric>(x: VectorVar2<T>) -> T {
+        (x[0] - 1.0).cosh().ln() + (x[1] + 2.0).cosh().ln()
+    }
+
+    struct LogCosh(Key);
+
+    impl ScalarCost for LogCosh {
+        fn keys(&self) -> &[Key] {
+            std::slice::from_ref(&self.0)
+        }
+
+        fn gradient(&self, values: &Values) -> DiffResult<dtype, VectorX> {
+            let x: &VectorVar2 = values.get_unchecked(self.0).expect("Missing key");
+            ForwardProp::<Const<2>>::gradient_1(log_cosh, x)
+        }
+    }
+
+    #[test]
+    fn non_least_squares() {
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::new(5.0, 3.0));
+
+        let mut opt = Lbfgs::new();
+        tight(&mut opt);
+        opt.add_cost(LogCosh(X(0).into()));
+        let values = opt.optimize(values).expect("Optimization failed");
+
+        let x: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        assert_matrix_eq!(x.0, VectorVar2::new(1.0, -2.0).0, comp = abs, tol = TOL);
+    }
+
+    #[test]
+    fn factor_gradient() {
+        let prior = SO3::exp(Vector3::new(0.5, -1.0, 0.3).as_view());
+        let factor = FactorBuilder::new1_unchecked(PriorResidual::new(prior), X(0)).build();
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SO3::exp(Vector3::new(0.1, 0.2, -0.4).as_view()));
+
+        // Compare to central differences in the tangent space
+        let DiffResult { value, diff } = ScalarCost::gradient(&factor, &values);
+        let x: &SO3 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let f = |d: VectorX| {
+            let mut values = Values::new();
+            values.insert_unchecked(X(0), x.oplus(d.as_view()));
+            factor.error(&values)
+        };
+        let h = 1e-4;
+        let e = |i| VectorX::from_fn(3, |k, _| if k == i { h } else { 0.0 });
+        let numerical = VectorX::from_fn(3, |i, _| (f(e(i)) - f(-e(i))) / (2.0 * h));
+
+        assert_eq!(value, factor.error(&values));
+        assert_matrix_eq!(diff, numerical, comp = abs, tol = 1e2 * TOL);
+    }
+
+    #[test]
+    fn pose_graph() {
+        let graph = || {
+            let mut graph = Graph::new();
+            let res = PriorResidual::new(SE2::identity());
+            graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            for (i, delta) in (0..).zip([(1.0, 0.2, 0.0), (0.8, 1.1, 0.1), (0.5, 0.9, -0.3)]) {
+                let res = BetweenResidual::new(SE2::new(delta.0, delta.1, delta.2));
+                graph.add_factor(FactorBuilder::new2_unchecked(res, X(i), X(i + 1)).build());
+            }
+            let res = BetweenResidual::new(SE2::new(2.0, 1.5, 0.0));
+            graph.add_factor(FactorBuilder::new2_unchecked(res, X(0), X(3)).build());
+            graph
+        };
+        let mut values = Values::new();
+        for i in 0..4 {
+            values.insert_unchecked(X(i), SE2::identity());
+        }
+
+        // Gauss-Newton can stall from the identity, so damp the reference
+        let mut lm: LevenMarquardt = LevenMarquardt::new(graph());
+        lm.params_base.error_tol_absolute = TOL * TOL;
+        lm.params_base.error_tol_relative = 0.0;
+        let expected = lm.optimize(values.clone()).expect("Optimization failed");
+
+        let mut opt = Lbfgs::from_graph(graph());
+        tight(&mut opt);
+        let result = opt.optimize(values).expect("Optimization failed");
+
+        let error = graph().error(&result);
+        assert!(error <= graph().error(&expected) + TOL);
+
+        for i in 0..4 {
+            let x: &SE2 = result.get_unchecked(X(i)).expect("Missing X(i)");
+            let y: &SE2 = expected.get_unchecked(X(i)).expect("Missing X(i)");
+            assert_matrix_eq!(x.ominus(y), VectorX::zeros(3), comp = abs, tol = 1e2 * TOL);
+        }
+    }
+
+    struct Record(Rc<RefCell<Vec<IterationRecord>>>);
+
+    impl OptObserver for Record {
+        type Input = Values;
+
+        fn on_step(&self, _values: &Values, record: &IterationRecord) -> ObserverControl {
+            self.0.borrow_mut().push(record.clone());
+            ObserverControl::Continue
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::new(5.0, 3.0));
+        values.insert_unchecked(X(1), VectorVar2::new(1.0, 1.0));
+        values.fix(X(1));
+
+        let mut opt = Lbfgs::new();
+        opt.add_cost(LogCosh(X(0).into()));
+        opt.add_cost(LogCosh(X(1).into()));
+        let records = Rc::new(RefCell::new(Vec::new()));
+        opt.observers.add(Record(records.clone()));
+        let (result, summary) = opt.optimize_with_summary(values);
+        let values = result.expect("Optimization failed");
+
+        let records = records.borrow();
+        assert_eq!(records.len(), summary.iterations.len());
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.iteration, i + 1);
+            assert!(record.error_after <= record.error_before);
+            assert_eq!(record.factor_errors.len(), 2);
+        }
+
+        // Fixed variable is untouched
+        let x1: &VectorVar2 = values.get_unchecked(X(1)).expect("Missing X(1)");
+        assert_eq!(x1.0, VectorVar2::new(1.0, 1.0).0);
+    }
+}
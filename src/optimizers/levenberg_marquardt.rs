@@ -1,11 +1,17 @@
-use std::ops::Mul;
+use std::{
+    ops::Mul,
+    time::{Duration, Instant},
+};
 
 use faer::{scale, sparse::SparseColMat};
 use faer_ext::IntoNalgebra;
 
-use super::{OptError, OptObserverVec, OptParams, OptResult, Optimizer};
+use super::{
+    graph_order_cache::GraphOrderCache, macros::impl_with_solver, GraphOptimizer, IterationRecord,
+    IterationSummary, ObserverControl, OptError, OptObserverVec, OptParams, OptResult, Optimizer,
+};
 use crate::{
-    containers::{Graph, GraphOrder, Values, ValuesOrder},
+    containers::{ActiveBound, Graph, Values},
     dtype,
     linalg::DiffResult,
     linear::{CholeskySolver, LinearSolver, LinearValues},
@@ -16,6 +22,13 @@ pub struct LevenParams {
     pub lambda_max: dtype,
     pub lambda_factor: dtype,
     pub diagonal_damping: bool,
+    /// Use Nielsen's damping update, which scales lambda smoothly based on the
+    /// gain ratio rather than by a fixed factor
+    pub nielsen_damping: bool,
+    /// Add the exact second order terms of the Hessian for residuals that
+    /// provide them (see [Residual::residual_hessian](crate::residuals::Residual::residual_hessian)),
+    /// rather than only using the Gauss-Newton approximation
+    pub second_order: bool,
 }
 
 impl Default for LevenParams {
@@ -25,6 +38,8 @@ impl Default for LevenParams {
             lambda_max: 1e5,
             lambda_factor: 10.0,
             diagonal_damping: true,
+            nielsen_damping: false,
+            second_order: false,
         }
     }
 }
@@ -33,7 +48,29 @@ impl Default for LevenParams {
 ///
 /// Solves a damped version of the normal equations,  
 /// $$A^\top A \Delta \Theta + \lambda diag(A) = A^\top b$$
-/// each optimizer steps. Parameters can be modified using the `params_base` and
+/// each optimizer steps. A step is only accepted if it decreases the actual
+/// nonlinear error, with the damping updated based on the ratio between the
+/// actual and predicted decrease. Any bounds set on the values (see
+/// [Values::set_bounds]) are honored by projecting each step back into the
+/// bounds.
+///
+/// At a minimum with nonzero error no step can decrease the actual error, so
+/// every step would be rejected. Thus if a step is rejected and the decrease
+/// predicted by the linear model (not including second order terms, which can
+/// make the model nonconvex) is below
+/// [error_tol_absolute](OptParams::error_tol_absolute), the gradient has
+/// vanished. Rather than increasing the damping until `lambda_max` and
+/// failing, the step returns the values unchanged so the optimizer terminates
+/// normally, as [Dogleg](super::Dogleg) does.
+///
+/// With [second_order](LevenParams::second_order) set, $A^\top A$ is replaced
+/// by the full Hessian of the error wherever residuals provide their Hessians,
+/// giving a damped Newton method. This converges much faster for strongly
+/// nonlinear residuals, such as range or bearing measurements, far from the
+/// solution. If the Hessian is indefinite the linear solve fails, and the step
+/// is handled like a rejected one by increasing the damping.
+///
+/// Parameters can be modified using the `params_base` and
 /// `params_leven` fields, and observers add using `observers`. Additionally, is
 /// generic over the linear solver, but defaults to [CholeskySolver]. See the
 /// [linear](crate::linear) module for more linear solver options.
@@ -47,8 +84,11 @@ pub struct LevenMarquardt<S: LinearSolver = CholeskySolver> {
     /// Observers for the optimizer
     pub observers: OptObserverVec<Values>,
     lambda: dtype,
+    nu: dtype,
     // For caching computation between steps
-    graph_order: Option<GraphOrder>,
+    graph_order: GraphOrderCache,
+    summary: IterationSummary,
+    start: Instant,
 }
 
 impl<S: LinearSolver> LevenMarquardt<S> {
@@ -60,15 +100,31 @@ impl<S: LinearSolver> LevenMarquardt<S> {
             params_leven: LevenParams::default(),
             observers: OptObserverVec::default(),
             lambda: 1e-5,
-            graph_order: None,
+            nu: 2.0,
+            graph_order: GraphOrderCache::default(),
+            summary: IterationSummary::default(),
+            start: Instant::now(),
         }
     }
 
     pub fn graph(&self) -> &Graph {
         &self.graph
     }
+
+    // Returns false once lambda has passed its maximum
+    fn increase_lambda(&mut self) -> bool {
+        if self.params_leven.nielsen_damping {
+            self.lambda *= self.nu;
+            self.nu *= 2.0;
+        } else {
+            self.lambda *= self.params_leven.lambda_factor;
+        }
+        self.lambda <= self.params_leven.lambda_max
+    }
 }
 
+impl_with_solver!(LevenMarquardt);
+
 impl<S: LinearSolver> Optimizer for LevenMarquardt<S> {
     type Input = Values;
 
@@ -80,25 +136,33 @@ impl<S: LinearSolver> Optimizer for LevenMarquardt<S> {
         self.graph.error(values)
     }
 
-    fn init(&mut self, _values: &Values) {
-        // TODO: Some way to manual specify how to computer ValuesOrder
-        // Precompute the sparsity pattern
-        self.graph_order = Some(
-            self.graph
-                .sparsity_pattern(ValuesOrder::from_values(_values)),
-        );
+    fn init(&mut self, values: &Values) {
+        let graph_order = self
+            .graph_order
+            .init(&self.graph, values, &self.params_base.ordering);
+        self.solver.set_order(&graph_order.order);
+        self.start = Instant::now();
     }
 
-    // TODO: Some form of logging of the lambda value
-    // TODO: More sophisticated stopping criteria based on magnitude of the gradient
-    fn step(&mut self, mut values: Values, idx: usize) -> OptResult<Values> {
-        // Make an ordering
-        let order = ValuesOrder::from_values(&values);
+    fn active_bounds(&self, values: &Values) -> Vec<ActiveBound> {
+        values.active_bounds()
+    }
+
+    fn step_summary(&self) -> IterationSummary {
+        self.summary.clone()
+    }
+
+    fn notify(&self, values: &Values, record: IterationRecord) -> ObserverControl {
+        self.observers.notify_graph(&self.graph, values, record)
+    }
 
+    // TODO: More sophisticated stopping criteria based on magnitude of the gradient
+    fn step(&mut self, mut values: Values, _idx: usize) -> OptResult<Values> {
         // Solve the linear system
+        let start = Instant::now();
         let linear_graph = self.graph.linearize(&values);
         let DiffResult { value: r, diff: j } =
-            linear_graph.residual_jacobian(self.graph_order.as_ref().expect("Missing graph order"));
+            linear_graph.residual_jacobian(self.graph_order.get());
 
         // Form A
         let jtj = j
@@ -128,60 +192,356 @@ impl<S: LinearSolver> Optimizer for LevenMarquardt<S> {
         // Form b
         let b = j.as_ref().transpose().mul(&r);
 
+        let order = self.graph_order.get().order.clone();
+
+        // Add the terms Gauss-Newton leaves out of the Hessian
+        let second_order = if self.params_leven.second_order {
+            self.graph.second_order(&values, &order)
+        } else {
+            Vec::new()
+        };
+        let jtj = if second_order.is_empty() {
+            jtj
+        } else {
+            let s = SparseColMat::try_new_from_triplets(jtj.nrows(), jtj.ncols(), &second_order)
+                .expect("Failed to make second order terms");
+            &jtj + &s
+        };
         let mut dx = LinearValues::zero_from_order(order.clone());
-        let old_error = linear_graph.error(&dx);
+        let linear_error_old = linear_graph.error(&dx);
+        let error_old = self.graph.error(&values);
+        self.summary = IterationSummary {
+            time_linearize: start.elapsed(),
+            ..Default::default()
+        };
 
+        let mut time_solve = Duration::ZERO;
+        let mut time_update = Duration::ZERO;
         loop {
             // Make Ax = b
             let a = &jtj + (&i * scale(self.lambda));
 
             // Solve Ax = b
-            let delta = self
-                .solver
-                .solve_symmetric(a.as_ref(), b.as_ref())
-                .as_ref()
-                .into_nalgebra()
-                .column(0)
-                .clone_owned();
-            dx = LinearValues::from_order_and_vector(
-                self.graph_order
-                    .as_ref()
-                    .expect("Missing graph order")
-                    .order
-                    .clone(),
-                delta,
-            );
-
-            // Update our cost
-            let curr_error = linear_graph.error(&dx);
-
-            if curr_error < old_error {
+            let start = Instant::now();
+            let delta = match self.solver.solve_symmetric(a.as_ref(), b.as_ref()) {
+                Ok(delta) => delta.as_ref().into_nalgebra().column(0).clone_owned(),
+                Err(e) => {
+                    // More damping may make the system solvable, so treat it as a rejected step
+                    time_solve += start.elapsed();
+                    if !self.increase_lambda() {
+                        return Err(OptError::invalid_system(e, &order));
+                    }
+                    log::warn!(
+                        "Linear solver failed with {:?}, increasing lambda to {:.3e}",
+                        e,
+                        self.lambda
+                    );
+                    values = self.params_base.interrupted(self.start, values)?;
+                    continue;
+                }
+            };
+            time_solve += start.elapsed();
+
+            // Keep bounded variables within their bounds
+            let mut delta = delta;
+            for (key, idx) in order.iter() {
+                if values.is_bounded(*key) {
+                    let projected = values.project_step(*key, delta.rows(idx.idx, idx.dim));
+                    delta.rows_mut(idx.idx, idx.dim).copy_from(&projected);
+                }
+            }
+            let step_norm = delta.norm();
+            let curvature = second_order
+                .iter()
+                .map(|(i, j, v)| v * delta[*i] * delta[*j])
+                .sum::<dtype>();
+            dx = LinearValues::from_order_and_vector(order.clone(), delta);
+
+            // Compare the actual decrease to what the quadratic model predicted
+            let start = Instant::now();
+            let mut values_new = values.clone();
+            values_new.oplus_mut(&dx);
+            let decrease_actual = error_old - self.graph.error(&values_new);
+            let decrease_linear = linear_error_old - linear_graph.error(&dx);
+            let decrease_pred = decrease_linear - curvature / 2.0;
+            time_update += start.elapsed();
+            self.summary.time_solve = time_solve;
+            self.summary.time_update = time_update;
+            self.summary.lambda = Some(self.lambda);
+
+            if decrease_actual > 0.0 && decrease_pred > 0.0 {
+                let rho = decrease_actual / decrease_pred;
+                if self.params_leven.nielsen_damping {
+                    self.lambda *= (1.0 - (2.0 * rho - 1.0).powi(3)).max(1.0 / 3.0);
+                    self.nu = 2.0;
+                } else {
+                    self.lambda /= self.params_leven.lambda_factor;
+                }
+                self.lambda = self.lambda.max(self.params_leven.lambda_min);
+                self.summary.step_norm = step_norm;
+                values = values_new;
                 break;
             }
 
-            self.lambda *= self.params_leven.lambda_factor;
-            if self.lambda > self.params_leven.lambda_max {
+            // Step was rejected. If even the linear model can't decrease the
+            // error, the gradient has vanished and we're already at a minimum
+            if decrease_linear.abs() <= self.params_base.error_tol_absolute {
+                log::info!("Predicted decrease is below absolute tolerance, at a minimum");
+                return Ok(values);
+            }
+
+            // Otherwise increase the damping and try again
+            if !self.increase_lambda() {
                 return Err(OptError::FailedToStep);
             }
+            values = self.params_base.interrupted(self.start, values)?;
         }
 
-        // Update the values
-        values.oplus_mut(&dx);
-        self.lambda /= self.params_leven.lambda_factor;
-        if self.lambda < self.params_leven.lambda_min {
-            self.lambda = self.params_leven.lambda_min;
-        }
+        Ok(values)
+    }
+}
 
-        self.observers.notify(&values, idx);
+impl<S: LinearSolver> GraphOptimizer for LevenMarquardt<S> {
+    fn new(graph: Graph) -> Self {
+        Self::new(graph)
+    }
 
-        Ok(values)
+    fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    fn graph_mut(&mut self) -> &mut Graph {
+        self.graph_order.clear();
+        &mut self.graph
+    }
+
+    fn add_factors(&mut self, factors: Graph) {
+        for factor in factors {
+            self.graph.add_factor(factor);
+        }
+        self.graph_order.add_factors();
+    }
+
+    fn add_values(&mut self, values: &Values) {
+        self.graph_order.add_values(values);
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_optimizer;
+    use crate::{
+        containers::{FactorBuilder, Key},
+        linalg::{vectorx, Const, ForwardProp, MatrixX, Numeric, Vector3, VectorX},
+        optimizers::{test::optimize_between, CancellationToken, TerminationReason},
+        residuals::{PriorResidual, Residual1},
+        symbols::X,
+        test_optimizer,
+        variables::{Variable, VectorVar2, VectorVar3, SE3, SO3},
+    };
 
     test_optimizer!(LevenMarquardt);
+
+    #[test]
+    fn nielsen() {
+        let f = |graph| {
+            let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+            opt.params_leven.nielsen_damping = true;
+            opt
+        };
+        optimize_between::<_, 6, 12, SE3>(&f);
+    }
+
+    #[test]
+    fn error_decreases() {
+        // Far enough away that the linearization is poor
+        let prior = SO3::exp(Vector3::new(2.5, -1.0, 0.5).as_view());
+
+        for nielsen in [false, true] {
+            let mut graph = Graph::new();
+            let res = PriorResidual::new(prior.clone());
+            graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            let mut values = Values::new();
+            values.insert_unchecked(X(0), SO3::identity());
+
+            let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+            opt.params_leven.nielsen_damping = nielsen;
+            opt.init(&values);
+            for i in 0..10 {
+                let error_old = opt.error(&values);
+                values = opt.step(values, i).expect("Failed to step");
+                assert!(opt.error(&values) <= error_old);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_minimum() {
+        // Two priors that disagree, started exactly at the minimum
+        let mut graph = Graph::new();
+        for p in [VectorVar2::new(1.0, 2.0), VectorVar2::new(3.0, 2.0)] {
+            graph.add_factor(FactorBuilder::new1_unchecked(PriorResidual::new(p), X(0)).build());
+        }
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::new(2.0, 2.0));
+
+        // No step can decrease the error, but that's not a failure
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+        let (result, summary) = opt.optimize_with_summary(values);
+        let values = result.expect("Optimization failed at the minimum");
+        assert_eq!(summary.iterations.len(), 1);
+        assert_eq!(summary.termination, TerminationReason::AbsoluteTol);
+        let x: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        assert_eq!(x.0, VectorVar2::new(2.0, 2.0).0);
+    }
+
+    #[test]
+    fn summary() {
+        let prior = SO3::exp(Vector3::new(0.5, -1.0, 0.3).as_view());
+        let mut graph = Graph::new();
+        graph.add_factor(FactorBuilder::new1_unchecked(PriorResidual::new(prior), X(0)).build());
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SO3::identity());
+
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(result.is_ok());
+        assert_eq!(summary.lambda_history().len(), summary.iterations.len());
+        assert!(summary.lambda_history().iter().all(|l| *l > 0.0));
+    }
+
+    #[test]
+    fn bounds() {
+        let mut graph = Graph::new();
+        let res = PriorResidual::new(VectorVar3::new(3.0, -1.0, 1.5));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+        let res = PriorResidual::new(VectorVar2::new(1.0, 1.0));
+        graph.add_factor(FactorBuilder::new1_unchecked(res, X(1)).build());
+
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar3::new(1.0, 1.0, 1.0));
+        values.insert_unchecked(X(1), VectorVar2::identity());
+        values.set_bounds_unchecked(
+            X(0),
+            VectorVar3::new(0.0, 0.0, 0.0),
+            VectorVar3::new(2.0, 2.0, 2.0),
+        );
+
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph);
+        let (result, summary) = opt.optimize_with_summary(values);
+        let values = result.expect("Optimization failed");
+
+        let x0: &VectorVar3 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x1: &VectorVar2 = values.get_unchecked(X(1)).expect("Missing X(1)");
+        assert!((x0.0 - VectorVar3::new(2.0, 0.0, 1.5).0).norm() < 1e-4);
+        assert!((x1.0 - VectorVar2::new(1.0, 1.0).0).norm() < 1e-4);
+
+        let active = vec![
+            ActiveBound {
+                key: X(0).into(),
+                idx: 0,
+                upper: true,
+            },
+            ActiveBound {
+                key: X(0).into(),
+                idx: 1,
+                upper: false,
+            },
+        ];
+        assert_eq!(summary.active_bounds, active);
+    }
+
+    // Range to a known beacon, providing its Hessian
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    struct RangeResidual {
+        beacon: VectorVar2,
+        range: dtype,
+    }
+
+    #[factrs::mark]
+    impl Residual1 for RangeResidual {
+        type Differ = ForwardProp<Const<2>>;
+        type V1 = VectorVar2;
+        type DimIn = Const<2>;
+        type DimOut = Const<1>;
+
+        fn residual1<T: Numeric>(&self, x: VectorVar2<T>) -> VectorX<T> {
+            let d = x.0 - self.beacon.0.cast::<T>();
+            vectorx![d.norm() - T::from(self.range)]
+        }
+
+        fn residual1_hessian(&self, values: &Values, keys: &[Key]) -> Option<Vec<MatrixX>> {
+            Some(self.residual1_second_order(values, keys).hessians)
+        }
+    }
+
+    #[test]
+    fn second_order() {
+        // Inconsistent ranges, so the second order terms don't vanish
+        let beacons = [(0.0, 0.0, 5.5), (10.0, 0.0, 8.0), (0.0, 10.0, 6.0)];
+        let graph = || {
+            let mut graph = Graph::new();
+            for (x, y, range) in beacons {
+                let res = RangeResidual {
+                    beacon: VectorVar2::new(x, y),
+                    range,
+                };
+                graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            }
+            graph
+        };
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), VectorVar2::new(9.0, 9.0));
+
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph());
+        let (result, summary_gn) = opt.optimize_with_summary(values.clone());
+        let expected = result.expect("Optimization failed");
+
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph());
+        opt.params_leven.second_order = true;
+        let (result, summary) = opt.optimize_with_summary(values);
+        let values = result.expect("Optimization failed");
+
+        let x: &VectorVar2 = values.get_unchecked(X(0)).expect("Missing X(0)");
+        let x_exp: &VectorVar2 = expected.get_unchecked(X(0)).expect("Missing X(0)");
+        assert!((x.0 - x_exp.0).norm() < 1e-3);
+        assert!(summary.iterations.len() <= summary_gn.iterations.len());
+    }
+
+    #[test]
+    fn interrupted() {
+        let prior = SO3::exp(Vector3::new(0.5, -1.0, 0.3).as_view());
+        let graph = || {
+            let mut graph = Graph::new();
+            let res = PriorResidual::new(prior.clone());
+            graph.add_factor(FactorBuilder::new1_unchecked(res, X(0)).build());
+            graph
+        };
+        let mut values = Values::new();
+        values.insert_unchecked(X(0), SO3::identity());
+
+        // Cancelled before starting, the initial values come back untouched
+        let cancel = CancellationToken::new();
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph());
+        opt.params_base.cancel = cancel.clone();
+        cancel.cancel();
+        let (result, summary) = opt.optimize_with_summary(values.clone());
+        let Err(OptError::Cancelled(result)) = result else {
+            panic!("Expected optimization to be cancelled");
+        };
+        assert_eq!(summary.termination, TerminationReason::Cancelled);
+        assert!(summary.iterations.is_empty());
+        assert_eq!(opt.error(&result), opt.error(&values));
+
+        // Resetting the token lets it run again
+        cancel.reset();
+        assert!(opt.optimize(values.clone()).is_ok());
+
+        let mut opt: LevenMarquardt = LevenMarquardt::new(graph());
+        opt.params_base.max_time = Some(Duration::ZERO);
+        let (result, summary) = opt.optimize_with_summary(values);
+        assert!(matches!(result, Err(OptError::TimeLimit(_))));
+        assert_eq!(summary.termination, TerminationReason::TimeLimit);
+    }
 }
@@ -45,3 +45,22 @@ macro_rules! test_optimizer {
         }
     };
 }
+
+// Constructor for optimizers that are generic over their linear solver, all of
+// which keep it in a `solver` field
+macro_rules! impl_with_solver {
+    ($o:ident) => {
+        impl<S: $crate::linear::LinearSolver> $o<S> {
+            /// Create an optimizer with a specific linear solver, for solvers
+            /// that need to be configured such as
+            /// [SchurSolver](crate::linear::SchurSolver)
+            pub fn with_solver(graph: $crate::containers::Graph, solver: S) -> Self {
+                Self {
+                    solver,
+                    ..Self::new(graph)
+                }
+            }
+        }
+    };
+}
+pub(crate) use impl_with_solver;
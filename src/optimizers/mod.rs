@@ -21,29 +21,78 @@
 //!
 //! which can then be solved. For example, Gauss-Newton solves this directly,
 //! while Levenberg-Marquardt adds a damping term to the diagonal of $A^\top A$
-//! to ensure positive definiteness.
+//! to ensure positive definiteness. Dogleg instead keeps a trust region and
+//! blends the Gauss-Newton and steepest descent steps within it.
+//!
+//! For online problems where factors and variables arrive over time, [Isam2]
+//! keeps the eliminated system around and only refactors the parts touched by
+//! each update. If re-running a batch optimizer on a growing graph is preferred,
+//! [add_factors](GraphOptimizer::add_factors) and
+//! [add_values](GraphOptimizer::add_values) keep its variable ordering and
+//! extend the cached sparsity pattern. Alternatively, [FixedLagSmoother] keeps
+//! a bounded window of recent variables, marginalizing out older ones. For
+//! outlier rejection without good initialization, [Gnc] wraps another optimizer and gradually anneals the
+//! robust kernels. Similarly, [AugmentedLagrangian] wraps another optimizer to
+//! enforce hard equality constraints, marked with a
+//! [ConstrainedNoise](crate::noise::ConstrainedNoise).
+//!
+//! For objectives that aren't a sum of squares, [Lbfgs] minimizes a sum of
+//! general [ScalarCost]s using only their gradients.
 //!
 //! This module provides a set of optimizers that can be used to solve
 //! non-linear least squares problems. Each optimizer implements the [Optimizer]
 //! trait to give similar structure and usage.
 //!
 //! Additionally observers can be added to the optimizer to monitor the progress
-//! of the optimization. A prebuilt [Rerun](https://rerun.io/) can be enabled via
-//! the `rerun` feature.
+//! of the optimization. Each receives an [IterationRecord] after every
+//! iteration and can stop the optimization early. A prebuilt [Rerun](https://rerun.io/) can be enabled via
+//! the `rerun` feature. For diagnostics after the fact, such as the error and
+//! timing of each iteration and why the optimizer stopped, use
+//! [optimize_with_summary](Optimizer::optimize_with_summary) to get an
+//! [OptSummary].
+//!
+//! For real-time use, [OptParams] can bound the wall time of each call to
+//! optimize and hold a [CancellationToken] to stop it from another thread. In
+//! either case the values with the lowest error found so far are returned in
+//! the error.
 //!
 //! If you desire to implement your own optimizer, we additionally recommend
 //! using the [test_optimizer](crate::test_optimizer) macro to run a handful of
 //! simple tests over a few different variable types to ensure correctness.
 mod traits;
-pub use traits::{OptError, OptObserver, OptObserverVec, OptParams, OptResult, Optimizer};
+pub use traits::{
+    CancellationToken, GraphOptimizer, IterationRecord, IterationSummary, ObserverControl,
+    OptError, OptObserver, OptObserverVec, OptParams, OptResult, OptSummary, Optimizer,
+    TerminationReason,
+};
 
 mod macros;
 
+mod graph_order_cache;
+
 mod gauss_newton;
-pub use gauss_newton::GaussNewton;
+pub use gauss_newton::{GaussNewton, GaussNewtonParams, LineSearch};
 
 mod levenberg_marquardt;
-pub use levenberg_marquardt::LevenMarquardt;
+pub use levenberg_marquardt::{LevenMarquardt, LevenParams};
+
+mod dogleg;
+pub use dogleg::{Dogleg, DoglegParams};
+
+mod isam2;
+pub use isam2::{Isam2, Isam2Params, Isam2Update};
+
+mod fixed_lag;
+pub use fixed_lag::FixedLagSmoother;
+
+mod gnc;
+pub use gnc::{Gnc, GncParams};
+
+mod augmented_lagrangian;
+pub use augmented_lagrangian::{AugLagParams, AugmentedLagrangian};
+
+mod lbfgs;
+pub use lbfgs::{Lbfgs, LbfgsParams, ScalarCost};
 
 // These aren't tests themselves, but are helpers to test optimizers
 #[cfg(test)]
@@ -1,17 +1,82 @@
-use crate::dtype;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    containers::{ActiveBound, Graph, Key, OrderingStrategy, Values, ValuesOrder},
+    dtype,
+    linear::LinearSolverError,
+};
 
 /// Error types for optimizers
 #[derive(Debug)]
 pub enum OptError<Input> {
     MaxIterations(Input),
-    InvalidSystem,
+    /// The linear system couldn't be solved, contains the variable at the
+    /// offending pivot if it could be found
+    InvalidSystem {
+        error: LinearSolverError,
+        key: Option<Key>,
+    },
+    /// A factor references a variable that has no value
+    MissingKey(Key),
     FailedToStep,
+    /// No step length along the search direction sufficiently decreased the
+    /// error
+    LineSearchFailed,
+    /// The [CancellationToken] was triggered, contains the values with the
+    /// lowest error found so far
+    Cancelled(Input),
+    /// Ran out of [max_time](OptParams::max_time), contains the values with the
+    /// lowest error found so far
+    TimeLimit(Input),
+}
+
+impl<Input> OptError<Input> {
+    /// Wrap a linear solver error, looking up the variable at its pivot
+    pub fn invalid_system(error: LinearSolverError, order: &ValuesOrder) -> Self {
+        let key = error.pivot().and_then(|p| order.key_at(p));
+        log::warn!("Linear solver failed with {:?} at {:?}", error, key);
+        Self::InvalidSystem { error, key }
+    }
 }
 
 /// Result type for optimizers
 pub type OptResult<Input> = Result<Input, OptError<Input>>;
 
 // ------------------------- Optimizer Params ------------------------- //
+/// Shared flag to stop an optimizer early
+///
+/// Clones share the same flag, so a clone can be placed in [OptParams] and
+/// another kept to [cancel](CancellationToken::cancel) from a different
+/// thread. The flag stays set until it's [reset](CancellationToken::reset).
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request that any optimizer holding this token stops
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag so the token can be used again
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 /// Parameters for the optimizer
 #[derive(Debug, Clone)]
 pub struct OptParams {
@@ -19,6 +84,12 @@ pub struct OptParams {
     pub error_tol_relative: dtype,
     pub error_tol_absolute: dtype,
     pub error_tol: dtype,
+    /// How to order the variables in the linear system
+    pub ordering: OrderingStrategy,
+    /// Maximum wall time for a single call to optimize
+    pub max_time: Option<Duration>,
+    /// Checked between iterations to stop early
+    pub cancel: CancellationToken,
 }
 
 impl Default for OptParams {
@@ -28,18 +99,144 @@ impl Default for OptParams {
             error_tol_relative: 1e-6,
             error_tol_absolute: 1e-6,
             error_tol: 0.0,
+            ordering: OrderingStrategy::default(),
+            max_time: None,
+            cancel: CancellationToken::default(),
         }
     }
 }
 
+impl OptParams {
+    /// Check if an optimizer that began at `start` should stop early, returning
+    /// the error to stop with
+    pub fn interrupted<I>(&self, start: Instant, values: I) -> Result<I, OptError<I>> {
+        if self.cancel.is_cancelled() {
+            log::info!("Optimization was cancelled, stopping optimization");
+            Err(OptError::Cancelled(values))
+        } else if self.max_time.is_some_and(|t| start.elapsed() >= t) {
+            log::info!("Time limit reached, stopping optimization");
+            Err(OptError::TimeLimit(values))
+        } else {
+            Ok(values)
+        }
+    }
+
+    // Whether an optimization could be stopped early, either by the time limit
+    // or by a clone of the cancellation token held elsewhere
+    fn interruptible(&self) -> bool {
+        self.max_time.is_some() || Arc::strong_count(&self.cancel.0) > 1
+    }
+}
+
+// ------------------------- Optimizer Summary ------------------------- //
+/// Reason the optimizer stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminationReason {
+    /// Error fell below [error_tol](OptParams::error_tol)
+    ErrorTol,
+    /// Error decrease fell below [error_tol_absolute](OptParams::error_tol_absolute)
+    AbsoluteTol,
+    /// Relative error decrease fell below [error_tol_relative](OptParams::error_tol_relative)
+    RelativeTol,
+    /// Hit [max_iterations](OptParams::max_iterations)
+    MaxIterations,
+    /// The optimizer was unable to take a step
+    FailedToStep,
+    /// The linear system couldn't be solved
+    InvalidSystem,
+    /// The line search couldn't find an acceptable step length
+    LineSearchFailed,
+    /// The [CancellationToken] was triggered
+    Cancelled,
+    /// Ran out of [max_time](OptParams::max_time)
+    TimeLimit,
+    /// An [OptObserver] returned [ObserverControl::Stop]
+    ObserverStop,
+}
+
+/// Summary of a single iteration
+///
+/// Optimizers fill in everything but the error via
+/// [step_summary](Optimizer::step_summary), anything they don't track is left
+/// at its default.
+#[derive(Debug, Clone, Default)]
+pub struct IterationSummary {
+    /// Error after the iteration
+    pub error: dtype,
+    /// Norm of the accepted step
+    pub step_norm: dtype,
+    /// Damping used for the accepted step, for Levenberg-Marquardt
+    pub lambda: Option<dtype>,
+    /// Time spent linearizing the graph
+    pub time_linearize: Duration,
+    /// Time spent solving the linear system
+    pub time_solve: Duration,
+    /// Time spent updating the values
+    pub time_update: Duration,
+}
+
+/// Summary of an entire optimization
+///
+/// Returned alongside the result by
+/// [optimize_with_summary](Optimizer::optimize_with_summary).
+#[derive(Debug, Clone)]
+pub struct OptSummary {
+    /// Error of the initial values
+    pub error_initial: dtype,
+    /// Error of the final values
+    pub error_final: dtype,
+    /// Summary of each iteration taken
+    pub iterations: Vec<IterationSummary>,
+    /// Why the optimizer stopped
+    pub termination: TerminationReason,
+    /// Bounds active at the final values, for optimizers that support them
+    pub active_bounds: Vec<ActiveBound>,
+    /// Total time spent optimizing
+    pub time: Duration,
+}
+
+impl OptSummary {
+    /// Damping of each iteration, for optimizers that use it
+    pub fn lambda_history(&self) -> Vec<dtype> {
+        self.iterations.iter().filter_map(|i| i.lambda).collect()
+    }
+}
+
 // ------------------------- Optimizer Observers ------------------------- //
+/// Record of a single iteration, passed to each [OptObserver]
+#[derive(Debug, Clone, Default)]
+pub struct IterationRecord {
+    /// Index of the iteration, starting at 1
+    pub iteration: usize,
+    /// Error before the step
+    pub error_before: dtype,
+    /// Error after the step
+    pub error_after: dtype,
+    /// Norm of the accepted step
+    pub step_norm: dtype,
+    /// Damping used for the accepted step, for Levenberg-Marquardt
+    pub lambda: Option<dtype>,
+    /// Error of each factor after the step, in the same order as the graph
+    pub factor_errors: Vec<dtype>,
+}
+
+/// Signal returned by an [OptObserver] to control the optimization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObserverControl {
+    #[default]
+    Continue,
+    /// Stop the optimization, returning the current values
+    Stop,
+}
+
 /// Observer trait for optimization
 ///
-/// This trait is used to observe the optimization process. It is called at each
-/// step of the optimization process.
+/// This trait is used to observe the optimization process. It is called after
+/// each iteration of the optimization process, and can stop the optimization
+/// early by returning [ObserverControl::Stop].
 pub trait OptObserver {
     type Input;
-    fn on_step(&self, values: &Self::Input, time: f64);
+    fn on_step(&self, values: &Self::Input, record: &IterationRecord) -> ObserverControl;
 }
 
 /// Observer collection for optimization
@@ -56,10 +253,36 @@ impl<I> OptObserverVec<I> {
         self.observers.push(boxed);
     }
 
-    pub fn notify(&self, values: &I, idx: usize) {
-        for callback in &self.observers {
-            callback.on_step(values, idx as f64);
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /// Notify all observers, stopping if any of them asked to
+    pub fn notify(&self, values: &I, record: &IterationRecord) -> ObserverControl {
+        self.observers.iter().fold(
+            ObserverControl::Continue,
+            |control, callback| match callback.on_step(values, record) {
+                ObserverControl::Stop => ObserverControl::Stop,
+                ObserverControl::Continue => control,
+            },
+        )
+    }
+}
+
+impl OptObserverVec<Values> {
+    /// Notify all observers, filling in the error of each factor in the graph
+    /// first
+    pub(crate) fn notify_graph(
+        &self,
+        graph: &Graph,
+        values: &Values,
+        mut record: IterationRecord,
+    ) -> ObserverControl {
+        if self.is_empty() {
+            return ObserverControl::Continue;
         }
+        record.factor_errors = graph.iter().map(|f| f.error(values)).collect();
+        self.notify(values, &record)
     }
 }
 
@@ -78,7 +301,7 @@ impl<I> Default for OptObserverVec<I> {
 /// optimizer, specifically a handful of stopping criteria and the main loop.
 pub trait Optimizer {
     /// Values the optimizer is optimizing
-    type Input;
+    type Input: Clone;
 
     /// Parameters for the optimizer
     fn params(&self) -> &OptParams;
@@ -90,19 +313,62 @@ pub trait Optimizer {
     fn error(&self, values: &Self::Input) -> dtype;
 
     /// Initialize the optimizer, optional
+    ///
+    /// Called at the start of each optimization, so optimizers that check
+    /// [interrupted](OptParams::interrupted) within a step should record the
+    /// start time here.
     fn init(&mut self, _values: &Self::Input) {}
 
-    // TODO: Custom logging based on optimizer
+    /// Summary of the most recent step, optional
+    ///
+    /// The error is filled in by the main loop, so can be left as is.
+    fn step_summary(&self) -> IterationSummary {
+        IterationSummary::default()
+    }
+
+    /// Bounds active at the given values, optional
+    fn active_bounds(&self, _values: &Self::Input) -> Vec<ActiveBound> {
+        Vec::new()
+    }
+
+    /// Pass a completed iteration to any observers, optional
+    fn notify(&self, _values: &Self::Input, _record: IterationRecord) -> ObserverControl {
+        ObserverControl::Continue
+    }
+
     /// Main optimization call function
-    fn optimize(&mut self, mut values: Self::Input) -> OptResult<Self::Input> {
+    fn optimize(&mut self, values: Self::Input) -> OptResult<Self::Input> {
+        self.optimize_with_summary(values).0
+    }
+
+    // TODO: Custom logging based on optimizer
+    /// Main optimization call function, additionally returning an
+    /// [OptSummary] of the run
+    fn optimize_with_summary(
+        &mut self,
+        mut values: Self::Input,
+    ) -> (OptResult<Self::Input>, OptSummary) {
+        let start = Instant::now();
+
         // Setup up everything from our values
         self.init(&values);
 
         // Check if we need to optimize at all
         let mut error_old = self.error(&values);
+        let mut summary = OptSummary {
+            error_initial: error_old,
+            error_final: error_old,
+            iterations: Vec::new(),
+            termination: TerminationReason::MaxIterations,
+            active_bounds: Vec::new(),
+            time: Duration::ZERO,
+        };
         if error_old <= self.params().error_tol {
             log::info!("Error is already below tolerance, skipping optimization");
-            return Ok(values);
+            summary.termination = TerminationReason::ErrorTol;
+            summary.active_bounds = self.active_bounds(&values);
+            summary.time = start.elapsed();
+            return (Ok(values), summary);
         }
 
         log::info!(
@@ -129,15 +395,76 @@ pub trait Optimizer {
 
         // Begin iterations
         let mut error_new = error_old;
+        // Best iterate seen, only kept once an optimizer that isn't monotone
+        // (such as Gauss-Newton without a line search) has moved past it. This
+        // needs a copy of each iterate, so is skipped if nothing can interrupt
+        let interruptible = self.params().interruptible();
+        let mut best: Option<(dtype, Self::Input)> = None;
         for i in 1..self.params().max_iterations + 1 {
             error_old = error_new;
-            values = self.step(values, i)?;
+            let previous = interruptible.then(|| values.clone());
+            values = match self
+                .params()
+                .interrupted(start, values)
+                .and_then(|values| self.step(values, i))
+            {
+                Ok(values) => values,
+                Err(e) => {
+                    // Stopped early, so hand back the best values found
+                    let e = match e {
+                        OptError::Cancelled(values) => {
+                            OptError::Cancelled(best_of(self, values, best))
+                        }
+                        OptError::TimeLimit(values) => {
+                            OptError::TimeLimit(best_of(self, values, best))
+                        }
+                        e => e,
+                    };
+                    summary.termination = match e {
+                        OptError::InvalidSystem { .. } => TerminationReason::InvalidSystem,
+                        OptError::LineSearchFailed => TerminationReason::LineSearchFailed,
+                        OptError::Cancelled(ref values) => {
+                            summary.active_bounds = self.active_bounds(values);
+                            TerminationReason::Cancelled
+                        }
+                        OptError::TimeLimit(ref values) => {
+                            summary.active_bounds = self.active_bounds(values);
+                            TerminationReason::TimeLimit
+                        }
+                        _ => TerminationReason::FailedToStep,
+                    };
+                    summary.time = start.elapsed();
+                    return (Err(e), summary);
+                }
+            };
 
             // Evaluate error again to see how we did
             error_new = self.error(&values);
+            summary.error_final = error_new;
+            if let Some(previous) = previous {
+                if error_new > error_old && !best.as_ref().is_some_and(|(e, _)| *e <= error_old) {
+                    best = Some((error_old, previous));
+                }
+            }
+            let step = IterationSummary {
+                error: error_new,
+                ..self.step_summary()
+            };
+            let control = self.notify(
+                &values,
+                IterationRecord {
+                    iteration: i,
+                    error_before: error_old,
+                    error_after: error_new,
+                    step_norm: step.step_norm,
+                    lambda: step.lambda,
+                    factor_errors: Vec::new(),
+                },
+            );
+            summary.iterations.push(step);
 
             let error_decrease_abs = error_old - error_new;
-            let error_decrease_rel = error_decrease_abs / error_old;
+            let error_decrease_rel = error_decrease_abs / error_old.abs();
 
             log::info!(
                 "{:^5} | {:^12.4e} | {:^12.4e} | {:^12.4e}",
@@ -148,20 +475,133 @@ pub trait Optimizer {
             );
 
             // Check if we need to stop
-            if error_new <= self.params().error_tol {
+            let termination = if error_new <= self.params().error_tol {
                 log::info!("Error is below tolerance, stopping optimization");
-                return Ok(values);
-            }
-            if error_decrease_abs <= self.params().error_tol_absolute {
+                Some(TerminationReason::ErrorTol)
+            } else if error_decrease_abs <= self.params().error_tol_absolute {
                 log::info!("Error decrease is below absolute tolerance, stopping optimization");
-                return Ok(values);
-            }
-            if error_decrease_rel <= self.params().error_tol_relative {
+                Some(TerminationReason::AbsoluteTol)
+            } else if error_decrease_rel <= self.params().error_tol_relative {
                 log::info!("Error decrease is below relative tolerance, stopping optimization");
-                return Ok(values);
+                Some(TerminationReason::RelativeTol)
+            } else if control == ObserverControl::Stop {
+                log::info!("Observer requested a stop, stopping optimization");
+                Some(TerminationReason::ObserverStop)
+            } else {
+                None
+            };
+            if let Some(termination) = termination {
+                summary.termination = termination;
+                summary.active_bounds = self.active_bounds(&values);
+                summary.time = start.elapsed();
+                return (Ok(values), summary);
+            }
+        }
+
+        summary.active_bounds = self.active_bounds(&values);
+        summary.time = start.elapsed();
+        (Err(OptError::MaxIterations(values)), summary)
+    }
+}
+
+// Whichever of the current and best values has the lower error
+fn best_of<O: Optimizer + ?Sized>(
+    opt: &O,
+    values: O::Input,
+    best: Option<(dtype, O::Input)>,
+) -> O::Input {
+    match best {
+        Some((error, best)) if error < opt.error(&values) => best,
+        _ => values,
+    }
+}
+
+/// Trait for optimizers that operate on a [Graph]
+///
+/// Allows for wrappers (such as
+/// [FixedLagSmoother](crate::optimizers::FixedLagSmoother)) to construct and
+/// modify the graph of an underlying optimizer.
+pub trait GraphOptimizer: Optimizer<Input = Values> {
+    /// Create the optimizer from a graph, using default parameters
+    fn new(graph: Graph) -> Self;
+
+    /// Reference to the graph being optimized
+    fn graph(&self) -> &Graph;
+
+    /// Mutable reference to the graph being optimized
+    ///
+    /// Since the graph may be changed arbitrarily, optimizers drop anything
+    /// they've cached about its structure.
+    fn graph_mut(&mut self) -> &mut Graph;
+
+    /// Append factors to the graph being optimized
+    ///
+    /// Unlike going through [graph_mut](GraphOptimizer::graph_mut), optimizers
+    /// may keep their variable ordering and only extend the sparsity pattern
+    /// for the new factors on the next optimization.
+    fn add_factors(&mut self, factors: Graph) {
+        let graph = self.graph_mut();
+        for factor in factors {
+            graph.add_factor(factor);
+        }
+    }
+
+    /// Register new variables that will be passed to the next optimization
+    ///
+    /// Optimizers that cache their variable ordering append these to the end
+    /// of it, rather than reordering everything. Does nothing by default.
+    fn add_values(&mut self, _values: &Values) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Takes a fixed sequence of steps on a scalar, cancelling after the last
+    struct Scripted {
+        params: OptParams,
+        steps: Vec<dtype>,
+        // Kept by the caller, such as on another thread
+        cancel: CancellationToken,
+    }
+
+    impl Optimizer for Scripted {
+        type Input = dtype;
+
+        fn params(&self) -> &OptParams {
+            &self.params
+        }
+
+        fn error(&self, x: &dtype) -> dtype {
+            x * x
+        }
+
+        fn step(&mut self, _x: dtype, idx: usize) -> OptResult<dtype> {
+            if idx == self.steps.len() {
+                self.cancel.cancel();
             }
+            Ok(self.steps[idx - 1])
         }
+    }
 
-        Err(OptError::MaxIterations(values))
+    #[test]
+    fn cancelled_returns_best() {
+        // Not monotone, so the last iterate isn't the best. The decrease
+        // tolerances would stop as soon as the error goes up, so disable them
+        let cancel = CancellationToken::new();
+        let mut opt = Scripted {
+            params: OptParams {
+                error_tol_absolute: dtype::NEG_INFINITY,
+                error_tol_relative: dtype::NEG_INFINITY,
+                cancel: cancel.clone(),
+                ..Default::default()
+            },
+            steps: vec![1.0, 3.0, 2.0],
+            cancel,
+        };
+        let (result, summary) = opt.optimize_with_summary(4.0);
+        assert!(matches!(result, Err(OptError::Cancelled(x)) if x == 1.0));
+        assert_eq!(summary.termination, TerminationReason::Cancelled);
+        assert_eq!(summary.iterations.len(), 3);
     }
 }
@@ -1,11 +1,11 @@
 use rerun::{
     components::RotationQuat, Arrows2D, Arrows3D, AsComponents, Points2D, Points3D, Quaternion,
-    Rotation3D, Transform3D, Vec2D, Vec3D,
+    Rotation3D, Scalar, Transform3D, Vec2D, Vec3D,
 };
 
 use crate::{
     containers::Values,
-    optimizers::OptObserver,
+    optimizers::{IterationRecord, ObserverControl, OptObserver},
     variables::{MatrixLieGroup, VariableDtype, VectorVar2, VectorVar3, SE2, SE3, SO2, SO3},
 };
 /*
@@ -418,11 +418,32 @@ where
 {
     type Input = Values;
 
-    fn on_step(&self, values: &Values, idx: f64) {
-        self.rec.set_time_seconds("stable_time", idx);
+    #[allow(clippy::unnecessary_cast)]
+    fn on_step(&self, values: &Values, record: &IterationRecord) -> ObserverControl {
+        self.rec
+            .set_time_seconds("stable_time", record.iteration as f64);
         let sol: R = values.filter::<V>().collect();
         self.rec
             .log(self.topic.clone(), &sol)
             .expect("Failed to log topic");
+
+        // Scalars for plotting the convergence
+        let mut scalars = vec![
+            ("error", record.error_after),
+            ("step_norm", record.step_norm),
+        ];
+        if let Some(lambda) = record.lambda {
+            scalars.push(("lambda", lambda));
+        }
+        for (name, value) in scalars {
+            self.rec
+                .log(
+                    format!("{}/{}", self.topic, name),
+                    &Scalar::new(value as f64),
+                )
+                .expect("Failed to log scalar");
+        }
+
+        ObserverControl::Continue
     }
 }
@@ -28,6 +28,11 @@ impl<P: Variable> BetweenResidual<P> {
     pub fn new(delta: P) -> Self {
         Self { delta }
     }
+
+    /// The measured value $z$
+    pub fn delta(&self) -> &P {
+        &self.delta
+    }
 }
 
 #[factrs::mark]
@@ -0,0 +1,74 @@
+use crate::{
+    containers::{Key, Values},
+    linalg::{DiffResult, MatrixX, VectorX},
+    residuals::Residual,
+};
+
+/// Dense linear prior on a set of variables.
+///
+/// Usually the result of marginalizing variables out of a graph, such as in the
+/// [FixedLagSmoother](crate::optimizers::FixedLagSmoother). Specifically it
+/// computes $$
+/// R (v \ominus v_0) - d
+/// $$
+/// where $v_0$ is the linearization point of the variables. The Jacobian is
+/// held fixed at $R$, so this is only valid near $v_0$.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinearPriorResidual {
+    linearization: Values,
+    r: MatrixX,
+    d: VectorX,
+}
+
+impl LinearPriorResidual {
+    /// Create a new linear prior
+    ///
+    /// The columns of `r` are ordered by the keys of the factor it's placed
+    /// in, and `linearization` must contain the linearization point of each
+    /// of those keys.
+    pub fn new(linearization: Values, r: MatrixX, d: VectorX) -> Self {
+        assert!(
+            r.nrows() == d.len(),
+            "Mismatch between R and d in LinearPriorResidual::new"
+        );
+        Self {
+            linearization,
+            r,
+            d,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", typetag::serde)]
+impl Residual for LinearPriorResidual {
+    fn dim_in(&self) -> usize {
+        self.r.ncols()
+    }
+
+    fn dim_out(&self) -> usize {
+        self.r.nrows()
+    }
+
+    fn residual(&self, values: &Values, keys: &[Key]) -> VectorX {
+        let delta = keys
+            .iter()
+            .flat_map(|k| {
+                let v = values.get_raw(*k).expect("Key missing in values");
+                let v0 = self
+                    .linearization
+                    .get_raw(*k)
+                    .expect("Key missing in linearization point");
+                v.ominus_dyn(v0).iter().copied().collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        &self.r * VectorX::from_vec(delta) - &self.d
+    }
+
+    fn residual_jacobian(&self, values: &Values, keys: &[Key]) -> DiffResult<VectorX, MatrixX> {
+        DiffResult {
+            value: self.residual(values, keys),
+            diff: self.r.clone(),
+        }
+    }
+}
@@ -47,6 +47,13 @@
 //!     }
 //! }
 //! ```
+//!
+//! Residuals can also provide their exact Hessians, used by second order
+//! optimizers such as [LevenMarquardt](crate::optimizers::LevenMarquardt) with
+//! [second_order](crate::optimizers::LevenParams::second_order) set. These are
+//! computed with [SecondOrderProp](factrs::linalg::SecondOrderProp) by also
+//! implementing `residual1_hessian` to return
+//! `Some(self.residual1_second_order(values, keys).hessians)`.
 mod traits;
 pub use traits::{Residual, Residual1, Residual2, Residual3, Residual4, Residual5, Residual6};
 
@@ -59,5 +66,8 @@ pub use prior::PriorResidual;
 mod between;
 pub use between::BetweenResidual;
 
+mod linear_prior;
+pub use linear_prior::LinearPriorResidual;
+
 pub mod imu_preint;
 pub use imu_preint::{Accel, Gravity, Gyro, ImuCovariance, ImuPreintegrator};
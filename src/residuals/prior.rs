@@ -23,6 +23,11 @@ impl<P: VariableDtype> PriorResidual<P> {
     pub fn new(prior: P) -> Self {
         Self { prior }
     }
+
+    /// The prior value $z$
+    pub fn prior(&self) -> &P {
+        &self.prior
+    }
 }
 
 #[factrs::mark]
@@ -1,8 +1,13 @@
 use std::fmt::Debug;
 
+use downcast_rs::{impl_downcast, Downcast};
+
 use crate::{
     containers::{Key, Values},
-    linalg::{Diff, DiffResult, DimName, MatrixX, Numeric, VectorX},
+    linalg::{
+        DefaultAllocator, Diff, DiffResult, DimName, Dual2Vector, DualAllocator, MatrixX, Numeric,
+        SecondOrderProp, SecondOrderResult, VectorX,
+    },
     variables::{Variable, VariableDtype},
 };
 
@@ -14,8 +19,11 @@ type Alias<V, T> = <V as Variable>::Alias<T>;
 /// This trait is used to implement custom residuals. It is recommended to use
 /// one of the numbered residuals traits instead, and then call the
 /// [impl_residual](crate::impl_residual) macro to implement this trait.
+///
+/// A boxed residual can be downcast back to its concrete type, which is done
+/// by [Factor::residual_as](crate::containers::Factor::residual_as).
 #[cfg_attr(feature = "serde", typetag::serde(tag = "tag"))]
-pub trait Residual: Debug {
+pub trait Residual: Debug + Send + Sync + Downcast {
     fn dim_in(&self) -> usize;
 
     fn dim_out(&self) -> usize;
@@ -23,8 +31,19 @@ pub trait Residual: Debug {
     fn residual(&self, values: &Values, keys: &[Key]) -> VectorX;
 
     fn residual_jacobian(&self, values: &Values, keys: &[Key]) -> DiffResult<VectorX, MatrixX>;
+
+    /// Hessian of each output of the residual, with respect to the stacked
+    /// tangent spaces of the inputs
+    ///
+    /// Used by second order optimizers, which fall back to the Gauss-Newton
+    /// approximation for residuals that return None, the default.
+    fn residual_hessian(&self, _values: &Values, _keys: &[Key]) -> Option<Vec<MatrixX>> {
+        None
+    }
 }
 
+impl_downcast!(Residual);
+
 #[cfg(feature = "serde")]
 pub use register_residual as tag_residual;
 
@@ -86,6 +105,33 @@ macro_rules! residual_maker {
                     )*
                     Self::Differ::[<jacobian_ $num>](|$($name,)*| self.[<residual $num>]($($name,)*), $($name,)*)
                 }
+
+                /// Hessian of each output of the residual, if available
+                ///
+                /// Defaults to None, meaning second order optimizers use the
+                /// Gauss-Newton approximation for this residual. To opt in, implement this
+                #[doc="using [" [<residual $num _second_order>] "](Self::" [<residual $num _second_order>] ")."]
+                fn [<residual $num _hessian>](&self, _values: &Values, _keys: &[Key]) -> Option<Vec<MatrixX>> {
+                    None
+                }
+
+                #[doc="Wrapper that unpacks variables and computes hessians of [" [<residual $num>] "](Self::" [<residual $num>] ") using [SecondOrderProp]."]
+                fn [<residual $num _second_order>](&self, values: &Values, keys: &[Key]) -> SecondOrderResult
+                where
+                    $(
+                        Self::$var: 'static,
+                    )*
+                    DefaultAllocator: DualAllocator<Self::DimIn>,
+                    Dual2Vector<Self::DimIn>: Numeric,
+                {
+                    // Unwrap everything
+                    $(
+                        let $name: &Self::$var = values.get_unchecked(keys[$idx]).unwrap_or_else(|| {
+                            panic!("Key not found in values: {:?} with type {}", keys[$idx], std::any::type_name::<Self::$var>())
+                        });
+                    )*
+                    SecondOrderProp::<Self::DimIn>::[<hessian_ $num>](|$($name,)*| self.[<residual $num>]($($name,)*), $($name,)*)
+                }
             }
         }
     };
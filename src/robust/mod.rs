@@ -19,7 +19,9 @@
 //!
 //! Generally constant asymptotic behavior is the best at outlier rejection, but
 //! relies heavily on good initialization. Some work, such as Graduated
-//! Non-Convexity (GNC), has been shown to circumvent this requirement.
+//! Non-Convexity (GNC), has been shown to circumvent this requirement. For this
+//! use [GncGemanMcClure] or [GncTruncatedLeastSquares] along with the
+//! [Gnc](crate::optimizers::Gnc) optimizer.
 
 use std::fmt::Debug;
 
@@ -33,12 +35,35 @@ use crate::dtype;
 /// [NumericalDiff](crate::linalg::NumericalDiff) to check that the weight is
 /// correct.
 #[cfg_attr(feature = "serde", typetag::serde(tag = "tag"))]
-pub trait RobustCost: Debug {
+pub trait RobustCost: Debug + Send + Sync {
     /// Compute the loss \rho(x^2)
     fn loss(&self, d2: dtype) -> dtype;
 
     /// Compute the weight \rho'(x^2) / x
     fn weight(&self, d2: dtype) -> dtype;
+
+    /// Access to the shape parameter for graduated non-convexity
+    ///
+    /// Returns None by default, only GNC compatible kernels need to implement
+    /// this.
+    fn gnc(&mut self) -> Option<&mut dyn GncCost> {
+        None
+    }
+}
+
+/// Robust cost function compatible with Graduated Non-Convexity (GNC)
+///
+/// These kernels have an additional shape parameter $\mu$ that controls how
+/// convex they are. The [Gnc](crate::optimizers::Gnc) optimizer starts them
+/// near convex, and gradually anneals them back to the original kernel.
+pub trait GncCost {
+    /// Set $\mu$ so that the kernel is convex for all squared residuals up to
+    /// `d2_max`
+    fn init_mu(&mut self, d2_max: dtype);
+
+    /// Move $\mu$ one step back towards the original kernel, returning true
+    /// once it has been reached
+    fn step_mu(&mut self, factor: dtype) -> bool;
 }
 
 #[cfg(feature = "serde")]
@@ -249,6 +274,158 @@ impl Debug for GemanMcClure {
     }
 }
 
+// ------------------------- GNC Geman-McClure ------------------------- //
+/// Geman-McClure with a GNC shape parameter
+///
+/// Uses the surrogate $\frac{\mu c^2 x^2}{2} / (\mu c^2 + x^2)$, which is
+/// convex for large $\mu$ and is [GemanMcClure] at $\mu = 1$.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GncGemanMcClure {
+    c2: dtype,
+    mu: dtype,
+}
+
+impl GncGemanMcClure {
+    pub fn new(c: dtype) -> Self {
+        GncGemanMcClure { c2: c * c, mu: 1.0 }
+    }
+}
+
+impl Default for GncGemanMcClure {
+    fn default() -> Self {
+        GncGemanMcClure::new(1.3998)
+    }
+}
+
+#[factrs::mark]
+impl RobustCost for GncGemanMcClure {
+    fn loss(&self, d2: dtype) -> dtype {
+        let mc2 = self.mu * self.c2;
+        0.5 * mc2 * d2 / (mc2 + d2)
+    }
+
+    fn weight(&self, d2: dtype) -> dtype {
+        let mc2 = self.mu * self.c2;
+        let frac = mc2 / (mc2 + d2);
+        frac * frac
+    }
+
+    fn gnc(&mut self) -> Option<&mut dyn GncCost> {
+        Some(self)
+    }
+}
+
+impl GncCost for GncGemanMcClure {
+    fn init_mu(&mut self, d2_max: dtype) {
+        self.mu = (2.0 * d2_max / self.c2).max(1.0);
+    }
+
+    fn step_mu(&mut self, factor: dtype) -> bool {
+        self.mu = (self.mu / factor).max(1.0);
+        self.mu <= 1.0
+    }
+}
+
+impl Debug for GncGemanMcClure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GncGemanMcClure {{ c: {}, mu: {} }}",
+            self.c2.sqrt(),
+            self.mu
+        )
+    }
+}
+
+// ------------------------- GNC Truncated Least Squares ------------------------- //
+/// Truncated least squares with a GNC shape parameter
+///
+/// Quadratic for $x^2 \leq \frac{\mu}{\mu + 1} c^2$, constant for $x^2 \geq
+/// \frac{\mu + 1}{\mu} c^2$, with a smooth transition between. It's convex
+/// as $\mu \to 0$ and approaches truncated least squares as $\mu \to
+/// \infty$.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GncTruncatedLeastSquares {
+    c2: dtype,
+    mu: dtype,
+    mu_max: dtype,
+}
+
+impl GncTruncatedLeastSquares {
+    pub fn new(c: dtype) -> Self {
+        GncTruncatedLeastSquares {
+            c2: c * c,
+            mu: 1e4,
+            mu_max: 1e4,
+        }
+    }
+}
+
+impl Default for GncTruncatedLeastSquares {
+    fn default() -> Self {
+        GncTruncatedLeastSquares::new(1.0)
+    }
+}
+
+#[factrs::mark]
+impl RobustCost for GncTruncatedLeastSquares {
+    fn loss(&self, d2: dtype) -> dtype {
+        let mu = self.mu;
+        if d2 <= mu / (mu + 1.0) * self.c2 {
+            d2 / 2.0
+        } else if d2 >= (mu + 1.0) / mu * self.c2 {
+            self.c2 / 2.0
+        } else {
+            let c = self.c2.sqrt();
+            0.5 * (2.0 * c * d2.sqrt() * (mu * (mu + 1.0)).sqrt() - mu * (self.c2 + d2))
+        }
+    }
+
+    fn weight(&self, d2: dtype) -> dtype {
+        let mu = self.mu;
+        if d2 <= mu / (mu + 1.0) * self.c2 {
+            1.0
+        } else if d2 >= (mu + 1.0) / mu * self.c2 {
+            0.0
+        } else {
+            (self.c2 / d2).sqrt() * (mu * (mu + 1.0)).sqrt() - mu
+        }
+    }
+
+    fn gnc(&mut self) -> Option<&mut dyn GncCost> {
+        Some(self)
+    }
+}
+
+impl GncCost for GncTruncatedLeastSquares {
+    fn init_mu(&mut self, d2_max: dtype) {
+        let denom = 2.0 * d2_max - self.c2;
+        self.mu = if denom > 0.0 {
+            (self.c2 / denom).min(self.mu_max)
+        } else {
+            self.mu_max
+        };
+    }
+
+    fn step_mu(&mut self, factor: dtype) -> bool {
+        self.mu = (self.mu * factor).min(self.mu_max);
+        self.mu >= self.mu_max
+    }
+}
+
+impl Debug for GncTruncatedLeastSquares {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GncTruncatedLeastSquares {{ c: {}, mu: {} }}",
+            self.c2.sqrt(),
+            self.mu
+        )
+    }
+}
+
 // ------------------------- Welsch ------------------------- //
 #[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -392,5 +569,29 @@ macro_rules! test_robust {
 mod test {
     use super::*;
 
-    test_robust!(L2, L1, Huber, Fair, Cauchy, GemanMcClure, Welsch, Tukey);
+    test_robust!(
+        L2,
+        L1,
+        Huber,
+        Fair,
+        Cauchy,
+        GemanMcClure,
+        GncGemanMcClure,
+        GncTruncatedLeastSquares,
+        Welsch,
+        Tukey
+    );
+
+    #[test]
+    fn gnc_weight() {
+        // Check the weights are still correct away from the final kernel
+        let mut gm = GncGemanMcClure::default();
+        gm.init_mu(100.0);
+        test_weight(&gm, 3.0);
+
+        // Transition region of TLS is [1/8, 8] here
+        let mut tls = GncTruncatedLeastSquares::default();
+        tls.init_mu(4.0);
+        test_weight(&tls, 1.0);
+    }
 }
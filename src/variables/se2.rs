@@ -92,9 +92,10 @@ impl<T: Numeric> Variable for SE2<T> {
         } else {
             let A;
             let B;
-            if theta < T::from(1e-5) {
-                A = T::from(1.0);
-                B = T::from(0.0);
+            // Keep the second order terms, so Hessians through dual numbers are exact
+            if theta.abs() < T::from(1e-5) {
+                A = T::from(1.0) - theta * theta / T::from(6.0);
+                B = theta / T::from(2.0);
             } else {
                 A = theta.sin() / theta;
                 B = (T::from(1.0) - theta.cos()) / theta;
@@ -116,9 +117,10 @@ impl<T: Numeric> Variable for SE2<T> {
         } else {
             let A;
             let B;
-            if theta < T::from(1e-5) {
-                A = T::from(1.0);
-                B = T::from(0.0);
+            // Keep the second order terms, so Hessians through dual numbers are exact
+            if theta.abs() < T::from(1e-5) {
+                A = T::from(1.0) - theta * theta / T::from(6.0);
+                B = theta / T::from(2.0);
             } else {
                 A = theta.sin() / theta;
                 B = (T::from(1.0) - theta.cos()) / theta;
@@ -262,10 +264,30 @@ impl<T: Numeric> fmt::Debug for SE2<T> {
 
 #[cfg(test)]
 mod tests {
+    use matrixcompare::assert_matrix_eq;
+
     use super::*;
-    use crate::{test_lie, test_variable};
+    use crate::{linalg::vectorx, test_lie, test_variable};
+
+    #[cfg(not(feature = "f32"))]
+    const TOL: f64 = 1e-6;
+    #[cfg(feature = "f32")]
+    const TOL: f32 = 1e-3;
 
     test_variable!(SO2);
 
     test_lie!(SO2);
+
+    #[test]
+    fn exp_log_negative_angle() {
+        // Negative angles must not take the small angle expansion
+        let pi = std::f64::consts::PI as dtype;
+        let xi = vectorx![-pi / 2.0, 1.0, 0.0];
+        let x = SE2::exp(xi.as_view());
+        if !cfg!(feature = "fake_exp") {
+            let expected = Vector2::new(2.0 / pi, -2.0 / pi);
+            assert_matrix_eq!(x.xy(), expected, comp = abs, tol = TOL);
+        }
+        assert_matrix_eq!(x.log(), xi, comp = abs, tol = TOL);
+    }
 }
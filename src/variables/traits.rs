@@ -5,8 +5,8 @@ use downcast_rs::{impl_downcast, Downcast};
 use crate::{
     dtype,
     linalg::{
-        AllocatorBuffer, Const, DefaultAllocator, DimName, DualAllocator, DualVector, MatrixDim,
-        MatrixViewDim, Numeric, SupersetOf, VectorDim, VectorViewX, VectorX,
+        AllocatorBuffer, Const, DefaultAllocator, DimName, Dual2Vector, DualAllocator, DualVector,
+        MatrixDim, MatrixViewDim, Numeric, SupersetOf, VectorDim, VectorViewX, VectorX,
     },
 };
 
@@ -160,6 +160,42 @@ pub trait Variable: Clone + Sized + Display + Debug {
             casted.compose(&setup)
         }
     }
+
+    /// Setup group element using the tangent space with second order dual
+    /// numbers
+    ///
+    /// Same as [dual_exp](Self::dual_exp), but the resulting variable will
+    /// also carry second derivatives, as used by
+    /// [SecondOrderProp](crate::linalg::SecondOrderProp).
+    fn dual2_exp<N: DimName>(idx: usize) -> Self::Alias<Dual2Vector<N>>
+    where
+        DefaultAllocator: DualAllocator<N>,
+        Dual2Vector<N>: Numeric,
+    {
+        let mut tv: VectorX<Dual2Vector<N>> = VectorX::zeros(Self::DIM);
+        let n = VectorDim::<N>::zeros().shape_generic().0;
+        for (i, tvi) in tv.iter_mut().enumerate() {
+            tvi.v1 = num_dual::Derivative::derivative_generic(Const::<1>, n, idx + i)
+        }
+        Self::Alias::<Dual2Vector<N>>::exp(tv.as_view())
+    }
+
+    /// Applies the tangent vector in second order dual space
+    ///
+    /// Second order version of [dual](Self::dual).
+    fn dual2<N: DimName>(&self, idx: usize) -> Self::Alias<Dual2Vector<N>>
+    where
+        DefaultAllocator: DualAllocator<N>,
+        Dual2Vector<N>: Numeric + SupersetOf<Self::T>,
+    {
+        let casted: Self::Alias<Dual2Vector<N>> = self.cast::<Dual2Vector<N>>();
+        let setup: Self::Alias<Dual2Vector<N>> = Self::dual2_exp(idx);
+        if cfg!(feature = "left") {
+            setup.compose(&casted)
+        } else {
+            casted.compose(&setup)
+        }
+    }
 }
 
 /// The object safe version of [Variable].
@@ -168,16 +204,21 @@ pub trait Variable: Clone + Sized + Display + Debug {
 /// Implemented for all types that implement [Variable].
 // TODO: Rename to VariableGeneric? Something like that
 #[cfg_attr(feature = "serde", typetag::serde(tag = "tag"))]
-pub trait VariableSafe: Debug + Display + Downcast {
+pub trait VariableSafe: Debug + Display + Downcast + Send + Sync {
     fn clone_box(&self) -> Box<dyn VariableSafe>;
 
     fn dim(&self) -> usize;
 
     fn oplus_mut(&mut self, delta: VectorViewX);
+
+    /// [ominus](Variable::ominus) against another variable of the same type
+    ///
+    /// Panics if the two variables aren't the same type.
+    fn ominus_dyn(&self, other: &dyn VariableSafe) -> VectorX;
 }
 
 #[cfg_attr(feature = "serde", typetag::serde)]
-impl<V: Variable<T = dtype> + 'static> VariableSafe for V {
+impl<V: Variable<T = dtype> + Send + Sync + 'static> VariableSafe for V {
     fn clone_box(&self) -> Box<dyn VariableSafe> {
         Box::new((*self).clone())
     }
@@ -189,6 +230,13 @@ impl<V: Variable<T = dtype> + 'static> VariableSafe for V {
     fn oplus_mut(&mut self, delta: VectorViewX) {
         *self = self.oplus(delta);
     }
+
+    fn ominus_dyn(&self, other: &dyn VariableSafe) -> VectorX {
+        let other = other
+            .downcast_ref::<V>()
+            .expect("Mismatched variable types in ominus");
+        self.ominus(other)
+    }
 }
 
 impl_downcast!(VariableSafe);